@@ -0,0 +1,136 @@
+//! Compares the BVH layouts and split strategies on a few scenes: the time
+//! to build each hierarchy, and how fast it traces camera rays and rays
+//! bouncing off their hits in random directions. Run with
+//! `cargo bench --features bvh-cost`.
+//! Nested hierarchies, such as those of meshes and instanced geometry,
+//! keep the default settings.
+
+use raytracer::bvh::{take_nodes_visited, BvhLayout, BvhSettings, BvhSplit};
+use raytracer::ray::Ray;
+use raytracer::render::build_bvh;
+use raytracer::rtweekend::seed_rng;
+use raytracer::sampler::{sample_unit_sphere, SamplerKind};
+use raytracer::{scene_file, Hittable, RenderSettings, Scene};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const SEED: u64 = 1;
+const WIDTH: usize = 320;
+const HEIGHT: usize = 180;
+/// Times each hierarchy is traced, keeping the fastest.
+const RUNS: usize = 3;
+
+const SCENES: [&str; 4] = [
+    "random_scene",
+    "final_scene",
+    "scenes/instances.scene",
+    "scenes/cornell_mesh.scene",
+];
+
+/// Split, layout and most objects per leaf. The first is the hierarchy
+/// `BvhNode` built before leaves could hold several objects.
+const CONFIGS: [(BvhSplit, BvhLayout, usize); 6] = [
+    (BvhSplit::Median, BvhLayout::Tree, 1),
+    (BvhSplit::Median, BvhLayout::Flat, 1),
+    (BvhSplit::Median, BvhLayout::Tree, 4),
+    (BvhSplit::Median, BvhLayout::Flat, 4),
+    (BvhSplit::Sah, BvhLayout::Tree, 4),
+    (BvhSplit::Sah, BvhLayout::Flat, 4),
+];
+
+fn load(name: &str) -> Scene {
+    seed_rng(SEED);
+    match Scene::by_name(name) {
+        Some(scene) => scene,
+        None => scene_file::load(Path::new(name)).expect("cannot load scene"),
+    }
+}
+
+/// Camera rays through every pixel, and for each one that hits, a ray in a
+/// random direction from the hit point.
+fn rays(scene: &Scene, world: &dyn Hittable) -> (Vec<Ray>, Vec<Ray>) {
+    let camera = scene.camera(WIDTH as f64 / HEIGHT as f64);
+    let mut sampler = SamplerKind::Independent.build(SEED, 1);
+    let mut primary = Vec::with_capacity(WIDTH * HEIGHT);
+    let mut secondary = Vec::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            sampler.start_sample(x, y, 0);
+            let s = (x as f64 + 0.5) / WIDTH as f64;
+            let t = (y as f64 + 0.5) / HEIGHT as f64;
+            let ray = match camera.get_ray(s, t, sampler.as_mut()) {
+                Some(ray) => ray,
+                None => continue,
+            };
+            if let Some(rec) = world.hit(&ray, 0.001, f64::INFINITY) {
+                let dir = sample_unit_sphere(sampler.get_2d());
+                secondary.push(Ray::new(&rec.p, &dir, ray.tm));
+            }
+            primary.push(ray);
+        }
+    }
+    (primary, secondary)
+}
+
+/// Fastest time to trace `rays`, with the number of hits and nodes visited.
+fn trace(world: &dyn Hittable, rays: &[Ray]) -> (Duration, usize, u64) {
+    let mut best = Duration::MAX;
+    let mut hits = 0;
+    take_nodes_visited();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        hits = rays
+            .iter()
+            .filter(|ray| world.hit(ray, 0.001, f64::INFINITY).is_some())
+            .count();
+        best = best.min(start.elapsed());
+    }
+    (best, hits, take_nodes_visited() / RUNS as u64)
+}
+
+fn main() {
+    // Scene files refer to models and textures from the workspace root.
+    std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+    for name in SCENES {
+        let scene = load(name);
+        let mut settings = RenderSettings::for_scene(&scene);
+        // Every configuration traces the same rays.
+        let (reference, _) = build_bvh(&scene, &settings, SEED);
+        let (primary, secondary) = rays(&scene, reference.as_ref());
+        println!(
+            "{}: {} objects, {} camera rays, {} bounce rays",
+            name,
+            scene.world.objects.len(),
+            primary.len(),
+            secondary.len()
+        );
+        for (split, layout, max_leaf_size) in CONFIGS {
+            settings.bvh = BvhSettings {
+                split,
+                layout,
+                max_leaf_size,
+                ..BvhSettings::default()
+            };
+            let build_start = Instant::now();
+            let (world, _) = build_bvh(&scene, &settings, SEED);
+            let build_time = build_start.elapsed();
+            print!(
+                "  {:<6} {:<4} leaf {}  build {:>9.3?}",
+                BvhSplit::NAMES[split as usize],
+                BvhLayout::NAMES[layout as usize],
+                max_leaf_size,
+                build_time
+            );
+            for rays in [&primary, &secondary] {
+                let (time, hits, nodes) = trace(world.as_ref(), rays);
+                print!(
+                    "  {:>6.2} Mrays/s ({} hits, {:.1} nodes/ray)",
+                    rays.len() as f64 / time.as_secs_f64() / 1e6,
+                    hits,
+                    nodes as f64 / rays.len().max(1) as f64
+                );
+            }
+            println!();
+        }
+    }
+}
@@ -17,12 +17,27 @@ impl Aabb {
         }
     }
 
-    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.minimum + self.maximum) / 2.0
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let inv_dir = Vec3::new(1.0 / r.dir.x, 1.0 / r.dir.y, 1.0 / r.dir.z);
+        self.hit_inverse(r, &inv_dir, t_min, t_max)
+    }
+
+    /// [`Aabb::hit`] with the reciprocal of the ray direction computed in
+    /// advance, for testing many boxes against one ray.
+    pub fn hit_inverse(&self, r: &Ray, inv_dir: &Vec3, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
-            let inv_d = 1.0 / r.dir[a];
-            let mut t0 = (self.minimum[a] - r.orig[a]) * inv_d;
-            let mut t1 = (self.maximum[a] - r.orig[a]) * inv_d;
-            if inv_d < 0.0 {
+            let mut t0 = (self.minimum[a] - r.orig[a]) * inv_dir[a];
+            let mut t1 = (self.maximum[a] - r.orig[a]) * inv_dir[a];
+            if inv_dir[a] < 0.0 {
                 swap(&mut t0, &mut t1);
             }
             t_min = t0.max(t_min);
@@ -1,11 +1,13 @@
 use crate::aabb::Aabb;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{area_to_solid_angle, HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Point3;
 use crate::Vec3;
 use std::sync::Arc;
 
+const INFINITY: f64 = f64::INFINITY;
+
 pub struct XyRect {
     mp: Arc<dyn Material>,
     x0: f64,
@@ -54,6 +56,26 @@ impl Hittable for XyRect {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, INFINITY) {
+            Some(rec) => {
+                let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+                area_to_solid_angle(&rec, direction, area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        let a = self.x0 + u.0 * (self.x1 - self.x0);
+        let b = self.y0 + u.1 * (self.y1 - self.y0);
+        Point3::new(a, b, self.k) - *origin
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mp);
+    }
 }
 
 pub struct XzRect {
@@ -104,6 +126,26 @@ impl Hittable for XzRect {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, INFINITY) {
+            Some(rec) => {
+                let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+                area_to_solid_angle(&rec, direction, area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        let a = self.x0 + u.0 * (self.x1 - self.x0);
+        let b = self.z0 + u.1 * (self.z1 - self.z0);
+        Point3::new(a, self.k, b) - *origin
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mp);
+    }
 }
 
 pub struct YzRect {
@@ -154,4 +196,24 @@ impl Hittable for YzRect {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, INFINITY) {
+            Some(rec) => {
+                let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+                area_to_solid_angle(&rec, direction, area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        let a = self.y0 + u.0 * (self.y1 - self.y0);
+        let b = self.z0 + u.1 * (self.z1 - self.z0);
+        Point3::new(self.k, a, b) - *origin
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mp);
+    }
 }
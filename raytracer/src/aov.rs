@@ -0,0 +1,185 @@
+//! Arbitrary output variables: properties of the first hit that are averaged
+//! per pixel like the image itself and written next to it for compositing
+//! and denoising.
+
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rtweekend::hash_seed;
+use crate::vec3::Color;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Reflectance of the material, see [`Material::albedo`].
+    ///
+    /// [`Material::albedo`]: crate::material::Material::albedo
+    Albedo,
+    /// World-space surface normal, facing the camera.
+    Normal,
+    /// Distance from the camera.
+    Depth,
+    /// World-space position.
+    Position,
+    /// A color per material.
+    MaterialId,
+    /// Radiance emitted by the surface, or the background for rays that
+    /// leave the scene.
+    Emission,
+}
+
+impl Aov {
+    pub const NAMES: [&'static str; 6] = [
+        "albedo",
+        "normal",
+        "depth",
+        "position",
+        "material_id",
+        "emission",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "albedo" => Some(Self::Albedo),
+            "normal" => Some(Self::Normal),
+            "depth" => Some(Self::Depth),
+            "position" => Some(Self::Position),
+            "material_id" => Some(Self::MaterialId),
+            "emission" => Some(Self::Emission),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        Self::NAMES[*self as usize]
+    }
+
+    /// The value for the camera ray `r`, which hit `rec` first or, for
+    /// `None`, left the scene towards `background`. Only emission is nonzero
+    /// for such rays. `material_ids` numbers the materials of the scene.
+    pub fn value(
+        &self,
+        r: &Ray,
+        rec: Option<&HitRecord>,
+        background: &Color,
+        material_ids: &MaterialIds,
+    ) -> Color {
+        let rec = match rec {
+            Some(rec) => rec,
+            None if *self == Self::Emission => return *background,
+            None => return Color::zero(),
+        };
+        match self {
+            Self::Albedo => rec.mat_ptr.albedo(rec),
+            Self::Normal => rec.normal,
+            Self::Depth => {
+                let depth = rec.t * r.dir.length();
+                Color::new(depth, depth, depth)
+            }
+            Self::Position => rec.p,
+            Self::MaterialId => material_color(rec, material_ids),
+            Self::Emission => rec.mat_ptr.emitted(rec.u, rec.v, &rec.p),
+        }
+    }
+}
+
+/// Numbers the materials of a world in the order [`Hittable::materials`]
+/// visits them, so that the numbers stay the same from run to run.
+#[derive(Clone, Debug, Default)]
+pub struct MaterialIds {
+    /// Number by material address. The addresses are only compared, and
+    /// stay valid as long as the world keeps its materials alive.
+    ids: HashMap<usize, u32>,
+}
+
+impl MaterialIds {
+    pub fn new(world: &dyn Hittable) -> Self {
+        let mut ids = HashMap::new();
+        world.materials(&mut |material| {
+            let next = ids.len() as u32;
+            ids.entry(address(material)).or_insert(next);
+        });
+        Self { ids }
+    }
+
+    /// The number of `material`, or `None` if the world does not use it.
+    pub fn get(&self, material: &Arc<dyn Material>) -> Option<u32> {
+        self.ids.get(&address(material)).copied()
+    }
+}
+
+fn address(material: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(material) as *const u8 as usize
+}
+
+/// A color that tells the material at `rec` apart from others, by its number
+/// in `ids`. Materials the world does not use all get the same color.
+pub fn material_color(rec: &HitRecord, ids: &MaterialIds) -> Color {
+    let id = ids.get(&rec.mat_ptr).map_or(u64::MAX, u64::from);
+    let hash = hash_seed(&[id]);
+    Color::new(
+        (hash & 0xff) as f64 / 255.0,
+        (hash >> 8 & 0xff) as f64 / 255.0,
+        (hash >> 16 & 0xff) as f64 / 255.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::{Lambertian, Metal};
+    use crate::rtweekend::seed_rng;
+    use crate::scene::Scene;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Point3, Vec3};
+
+    /// Material colors of the back wall and the two side walls.
+    fn material_colors(scene: &Scene) -> Vec<Color> {
+        let ids = MaterialIds::new(&scene.world);
+        let origin = Point3::new(278.0, 278.0, -800.0);
+        [(278.0, 555.0), (1.0, 300.0), (554.0, 300.0)]
+            .iter()
+            .map(|&(x, z)| {
+                let ray = Ray::new(&origin, &(Vec3::new(x, 400.0, z) - origin), 0.0);
+                let rec = scene.world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+                material_color(&rec, &ids)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn material_colors_repeat_across_scene_builds() {
+        seed_rng(1);
+        let first = Scene::by_name("cornell_box").unwrap();
+        seed_rng(1);
+        let second = Scene::by_name("cornell_box").unwrap();
+        let colors = material_colors(&first);
+        assert_eq!(colors, material_colors(&second));
+        assert_ne!(colors[0], colors[1]);
+        assert_ne!(colors[1], colors[2]);
+        // Neither the thread nor the scenes built before matter.
+        let third = std::thread::spawn(|| {
+            seed_rng(1);
+            material_colors(&Scene::by_name("cornell_box").unwrap())
+        });
+        assert_eq!(colors, third.join().unwrap());
+    }
+
+    #[test]
+    fn materials_are_numbered_in_world_order() {
+        let matte: Arc<dyn Material> = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        let shiny: Arc<dyn Material> = Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.8), &0.0));
+        let unused: Arc<dyn Material> = Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.8), &0.5));
+        let mut world = HittableList::new();
+        for (x, material) in [(0.0, &shiny), (2.0, &matte), (4.0, &shiny)] {
+            let center = Point3::new(x, 0.0, 0.0);
+            world.add(Arc::new(Sphere::new(center, 1.0, material.clone())));
+        }
+        let ids = MaterialIds::new(&world);
+        assert_eq!(ids.get(&shiny), Some(0));
+        assert_eq!(ids.get(&matte), Some(1));
+        assert_eq!(ids.get(&unused), None);
+    }
+}
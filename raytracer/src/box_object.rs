@@ -4,7 +4,7 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::Point3;
+use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
 
 pub struct BoxObject {
@@ -77,4 +77,16 @@ impl Hittable for BoxObject {
         let output_box = Aabb::new(&self.box_min, &self.box_max);
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        self.sides.random(origin, u)
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.sides.materials(visit);
+    }
 }
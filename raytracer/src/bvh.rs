@@ -1,10 +1,181 @@
 use crate::aabb::{surrounding_box, Aabb};
 use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
+use crate::material::Material;
 use crate::ray::Ray;
 use crate::rtweekend::random_usize_range;
+use crate::vec3::{Point3, Vec3};
+#[cfg(feature = "bvh-cost")]
+use std::cell::Cell;
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+/// Cost of visiting a node relative to intersecting one object, for the
+/// surface area heuristic.
+const TRAVERSAL_COST: f64 = 0.5;
+
+/// Deepest hierarchy a [`FlatBvh`] can traverse.
+const STACK_SIZE: usize = 64;
+
+#[cfg(feature = "bvh-cost")]
+thread_local! {
+    static NODES_VISITED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Number of BVH nodes the current thread has visited since the last call.
+/// Only counted with the `bvh-cost` feature, as counting slows down every
+/// traversal; zero otherwise.
+#[cfg(feature = "bvh-cost")]
+pub fn take_nodes_visited() -> u64 {
+    NODES_VISITED.with(|n| n.replace(0))
+}
+
+/// Number of BVH nodes the current thread has visited since the last call.
+/// Only counted with the `bvh-cost` feature, as counting slows down every
+/// traversal; zero otherwise.
+#[cfg(not(feature = "bvh-cost"))]
+pub fn take_nodes_visited() -> u64 {
+    0
+}
+
+#[cfg(feature = "bvh-cost")]
+fn count_visits(nodes: u64) {
+    NODES_VISITED.with(|n| n.set(n.get() + nodes));
+}
+
+#[cfg(not(feature = "bvh-cost"))]
+#[inline(always)]
+fn count_visits(_nodes: u64) {}
+
+/// How [`BvhNode::build`] divides the objects of a node between its children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvhSplit {
+    /// Binned surface area heuristic: the axis and position that minimize
+    /// the expected cost of tracing a ray through the node.
+    Sah,
+    /// Half of the objects on each side along a random axis.
+    Median,
+}
+
+impl BvhSplit {
+    pub const NAMES: [&'static str; 2] = ["sah", "median"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sah" => Some(Self::Sah),
+            "median" => Some(Self::Median),
+            _ => None,
+        }
+    }
+}
+
+/// How a built hierarchy is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvhLayout {
+    /// A [`FlatBvh`], traversed front to back with an explicit stack.
+    Flat,
+    /// A tree of [`BvhNode`]s, traversed recursively.
+    Tree,
+}
+
+impl BvhLayout {
+    pub const NAMES: [&'static str; 2] = ["flat", "tree"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "flat" => Some(Self::Flat),
+            "tree" => Some(Self::Tree),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BvhSettings {
+    pub split: BvhSplit,
+    pub layout: BvhLayout,
+    /// Candidate split positions per axis are the borders between this many
+    /// equal bins of object centroids.
+    pub bins: usize,
+    /// Most objects a leaf may hold. Nodes this small only split when the
+    /// heuristic expects that to be cheaper.
+    pub max_leaf_size: usize,
+}
+
+impl Default for BvhSettings {
+    fn default() -> Self {
+        Self {
+            split: BvhSplit::Sah,
+            layout: BvhLayout::Flat,
+            bins: 16,
+            max_leaf_size: 4,
+        }
+    }
+}
+
+/// Shape of a built hierarchy, together with the hierarchies nested in its
+/// objects, such as those of groups, meshes and instances.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BvhStats {
+    /// Objects that are intersected as a whole.
+    pub objects: usize,
+    /// Inner nodes.
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Nodes on the longest path from the root to a leaf, both included.
+    pub depth: usize,
+    /// Expected cost of a ray that hits the root box, in object
+    /// intersections, by the surface area heuristic.
+    pub sah_cost: f64,
+    /// Time the top-level build took; nested hierarchies are built with the
+    /// scene.
+    pub build_time: Duration,
+}
+
+impl BvhStats {
+    /// Stats of `objects` tested one after another, as a [`HittableList`]
+    /// does.
+    pub fn of_list(objects: &[Arc<dyn Hittable>]) -> Self {
+        let mut stats = Self::default();
+        for object in objects {
+            let cost = stats.add_object(object.as_ref(), 0);
+            stats.sah_cost += cost;
+        }
+        stats
+    }
+
+    /// Counts `object`, reached after `depth` nodes, with the hierarchies
+    /// inside it, and returns the expected cost of intersecting it.
+    fn add_object(&mut self, object: &dyn Hittable, depth: usize) -> f64 {
+        match object.bvh_stats() {
+            Some(nested) => {
+                self.objects += nested.objects;
+                self.nodes += nested.nodes;
+                self.leaves += nested.leaves;
+                self.depth = self.depth.max(depth + nested.depth);
+                nested.sah_cost
+            }
+            None => {
+                self.objects += 1;
+                self.depth = self.depth.max(depth);
+                1.0
+            }
+        }
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} objects, {} nodes, {} leaves, depth {}, SAH cost {:.2}, built in {:?}",
+            self.objects, self.nodes, self.leaves, self.depth, self.sah_cost, self.build_time
+        )
+    }
+}
+
+/// Bounding volume hierarchy over a set of objects.
 #[derive(Clone)]
 pub struct BvhNode {
     pub left: Arc<dyn Hittable>,
@@ -12,70 +183,424 @@ pub struct BvhNode {
     pub box_0: Aabb,
 }
 
+/// Root of a tree of [`BvhNode`]s, which keeps the stats of its build.
+struct BvhTree {
+    root: Arc<dyn Hittable>,
+    stats: BvhStats,
+}
+
+/// Several objects that are cheaper to test one by one than to split.
+struct BvhLeaf {
+    objects: Vec<Arc<dyn Hittable>>,
+    box_0: Aabb,
+}
+
+/// An object with its bounds, computed once for the whole build.
+struct Primitive {
+    object: Arc<dyn Hittable>,
+    bbox: Aabb,
+    /// `bbox` clipped to the finite extent of all objects, which the surface
+    /// area heuristic weighs instead, as unbounded boxes have no finite area
+    /// or centroid.
+    clipped: Aabb,
+    centroid: Point3,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Bin {
+    count: usize,
+    bbox: Option<Aabb>,
+}
+
+/// Bounding volume hierarchy stored as one array of nodes in depth-first
+/// order, so that a node's first child directly follows it. Leaves refer to
+/// ranges of `objects`.
+pub struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    objects: Vec<Arc<dyn Hittable>>,
+    stats: BvhStats,
+}
+
+#[derive(Clone, Copy)]
+struct FlatNode {
+    bbox: Aabb,
+    /// First object of a leaf, or the second child of an inner node.
+    offset: u32,
+    /// Number of objects of a leaf; zero for inner nodes.
+    count: u16,
+    /// Axis the children of an inner node were split along.
+    axis: u8,
+}
+
+/// Hierarchy as built, over ranges of the reordered primitives.
+enum BuildNode {
+    Leaf {
+        bbox: Aabb,
+        start: usize,
+        count: usize,
+    },
+    Inner {
+        bbox: Aabb,
+        axis: usize,
+        left: Box<BuildNode>,
+        right: Box<BuildNode>,
+    },
+}
+
 impl BvhNode {
     pub fn new_list(list: HittableList, time0: f64, time1: f64) -> Arc<dyn Hittable> {
         Self::new_vec(list.objects, time0, time1)
     }
 
-    pub fn new_vec(
-        mut objects: Vec<Arc<dyn Hittable>>,
+    pub fn new_vec(objects: Vec<Arc<dyn Hittable>>, time0: f64, time1: f64) -> Arc<dyn Hittable> {
+        Self::build(objects, time0, time1, &BvhSettings::default()).0
+    }
+
+    /// Builds a hierarchy over the `objects` that have a bounding box over
+    /// `time0..time1`, laid out as `settings.layout` says. Objects without
+    /// one are tested one by one next to it, and no objects give an empty
+    /// list.
+    pub fn build(
+        objects: Vec<Arc<dyn Hittable>>,
         time0: f64,
         time1: f64,
-    ) -> Arc<dyn Hittable> {
-        let axis = random_usize_range(0, 2);
-        match objects.len() {
-            0 => {
-                panic!();
+        settings: &BvhSettings,
+    ) -> (Arc<dyn Hittable>, BvhStats) {
+        let build_start = Instant::now();
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for object in objects {
+            match object.bounding_box(time0, time1) {
+                Some(bbox) => bounded.push((object, bbox)),
+                None => unbounded.push(object),
             }
-            1 => Arc::new(Self {
-                left: objects[0].clone(),
-                right: objects[0].clone(),
-                box_0: objects[0].bounding_box(time0, time1).unwrap(),
-            }),
-            2 => {
-                objects.sort_by(|a, b| {
-                    a.bounding_box(time0, time1).unwrap().minimum[axis]
-                        .partial_cmp(&b.bounding_box(time0, time1).unwrap().minimum[axis])
-                        .unwrap()
-                });
-                let l = objects[0].clone();
-                let r = objects[1].clone();
-                let b_0 = surrounding_box(
-                    l.bounding_box(time0, time1).unwrap(),
-                    r.bounding_box(time0, time1).unwrap(),
-                );
-                Arc::new(Self {
-                    left: l,
-                    right: r,
-                    box_0: b_0,
-                })
+        }
+        let (mut objects, mut stats) = if bounded.is_empty() {
+            (Vec::new(), BvhStats::default())
+        } else {
+            let (bvh, stats) = build_bounded(bounded, settings);
+            (vec![bvh], stats)
+        };
+        for object in unbounded {
+            let cost = stats.add_object(object.as_ref(), 0);
+            stats.sah_cost += cost;
+            objects.push(object);
+        }
+        stats.build_time = build_start.elapsed();
+        let bvh = match objects.len() {
+            1 => objects.pop().unwrap(),
+            _ => Arc::new(HittableList { objects }),
+        };
+        (bvh, stats)
+    }
+}
+
+/// The hierarchy over `objects` and their bounding boxes.
+fn build_bounded(
+    objects: Vec<(Arc<dyn Hittable>, Aabb)>,
+    settings: &BvhSettings,
+) -> (Arc<dyn Hittable>, BvhStats) {
+    let boxes: Vec<Aabb> = objects.iter().map(|(_, bbox)| *bbox).collect();
+    let limits = finite_extent(&boxes);
+    let mut primitives: Vec<Primitive> = objects
+        .into_iter()
+        .map(|(object, bbox)| {
+            let clipped = clip(&bbox, &limits);
+            Primitive {
+                object,
+                bbox,
+                clipped,
+                centroid: clipped.centroid(),
             }
-            _ => {
-                objects.sort_by(|a, b| {
-                    a.bounding_box(time0, time1).unwrap().minimum[axis]
-                        .partial_cmp(&b.bounding_box(time0, time1).unwrap().minimum[axis])
-                        .unwrap()
-                });
-                let mut left_objects = objects;
-                let right_objects = left_objects.split_off(left_objects.len() / 2);
-                let l = Self::new_vec(left_objects, time0, time1);
-                let r = Self::new_vec(right_objects, time0, time1);
-                let b_0 = surrounding_box(
-                    l.bounding_box(time0, time1).unwrap(),
-                    r.bounding_box(time0, time1).unwrap(),
-                );
-                Arc::new(Self {
-                    left: l,
-                    right: r,
-                    box_0: b_0,
-                })
+        })
+        .collect();
+    let mut stats = BvhStats::default();
+    let root_area = clipped_bounds(&primitives).surface_area();
+    let root = build_node(&mut primitives, 0, settings, 1, &mut stats);
+    if root_area > 0.0 {
+        stats.sah_cost /= root_area;
+    }
+    let objects: Vec<Arc<dyn Hittable>> = primitives.into_iter().map(|p| p.object).collect();
+    let bvh: Arc<dyn Hittable> = match settings.layout {
+        // Deeper hierarchies, which only very skewed scenes give, would
+        // overflow the traversal stack.
+        BvhLayout::Flat if root.depth() <= STACK_SIZE => {
+            let mut nodes = Vec::new();
+            flatten(&root, &mut nodes);
+            Arc::new(FlatBvh {
+                nodes,
+                objects,
+                stats,
+            })
+        }
+        _ => Arc::new(BvhTree {
+            root: tree(&root, &objects),
+            stats,
+        }),
+    };
+    (bvh, stats)
+}
+
+impl BuildNode {
+    /// Nodes on the longest path from this one to a leaf, both included.
+    fn depth(&self) -> usize {
+        match self {
+            BuildNode::Leaf { .. } => 1,
+            BuildNode::Inner { left, right, .. } => 1 + left.depth().max(right.depth()),
+        }
+    }
+}
+
+fn bounds(primitives: &[Primitive]) -> Aabb {
+    primitives[1..]
+        .iter()
+        .fold(primitives[0].bbox, |b, p| surrounding_box(b, p.bbox))
+}
+
+fn clipped_bounds(primitives: &[Primitive]) -> Aabb {
+    primitives[1..]
+        .iter()
+        .fold(primitives[0].clipped, |b, p| surrounding_box(b, p.clipped))
+}
+
+/// The box spanned by the finite coordinates of `boxes`, per axis.
+fn finite_extent(boxes: &[Aabb]) -> Aabb {
+    let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for b in boxes {
+        for v in [b.minimum, b.maximum] {
+            for a in 0..3 {
+                if v[a].is_finite() {
+                    min[a] = min[a].min(v[a]);
+                    max[a] = max[a].max(v[a]);
+                }
+            }
+        }
+    }
+    for a in 0..3 {
+        if min[a] > max[a] {
+            min[a] = 0.0;
+            max[a] = 0.0;
+        }
+    }
+    Aabb::new(&min, &max)
+}
+
+/// `bbox` within `limits`, where NaN coordinates leave the box unbounded.
+fn clip(bbox: &Aabb, limits: &Aabb) -> Aabb {
+    let mut min = bbox.minimum;
+    let mut max = bbox.maximum;
+    for a in 0..3 {
+        let (lo, hi) = (limits.minimum[a], limits.maximum[a]);
+        min[a] = if min[a].is_nan() {
+            lo
+        } else {
+            min[a].clamp(lo, hi)
+        };
+        max[a] = if max[a].is_nan() {
+            hi
+        } else {
+            max[a].clamp(lo, hi)
+        };
+    }
+    Aabb::new(&min, &max)
+}
+
+/// Builds the subtree over `primitives`, which start at index `start` of all
+/// primitives, at `depth` and adds its shape to `stats`, with areas not yet
+/// divided by the root's.
+fn build_node(
+    primitives: &mut [Primitive],
+    start: usize,
+    settings: &BvhSettings,
+    depth: usize,
+    stats: &mut BvhStats,
+) -> BuildNode {
+    let bbox = bounds(primitives);
+    let clipped = clipped_bounds(primitives);
+    let area = clipped.surface_area();
+    let n = primitives.len();
+    // Leaf sizes are stored in 16 bits.
+    let max_leaf_size = settings.max_leaf_size.clamp(1, u16::MAX as usize);
+    let split = match settings.split {
+        BvhSplit::Sah => sah_split(primitives, &clipped, settings.bins, max_leaf_size),
+        BvhSplit::Median if n > max_leaf_size => {
+            let axis = random_usize_range(0, 2);
+            primitives.sort_by(|a, b| a.bbox.minimum[axis].total_cmp(&b.bbox.minimum[axis]));
+            Some((n / 2, axis))
+        }
+        BvhSplit::Median => None,
+    };
+    let (mid, axis) = match split {
+        Some(split) => split,
+        None => {
+            stats.leaves += 1;
+            stats.depth = stats.depth.max(depth);
+            let cost: f64 = primitives
+                .iter()
+                .map(|p| stats.add_object(p.object.as_ref(), depth))
+                .sum();
+            stats.sah_cost += area * cost;
+            return BuildNode::Leaf {
+                bbox,
+                start,
+                count: n,
+            };
+        }
+    };
+    stats.nodes += 1;
+    stats.sah_cost += area * TRAVERSAL_COST;
+    let (left, right) = primitives.split_at_mut(mid);
+    BuildNode::Inner {
+        bbox,
+        axis,
+        left: Box::new(build_node(left, start, settings, depth + 1, stats)),
+        right: Box::new(build_node(right, start + mid, settings, depth + 1, stats)),
+    }
+}
+
+/// Appends `node` and its subtree to `nodes` in depth-first order.
+fn flatten(node: &BuildNode, nodes: &mut Vec<FlatNode>) {
+    match node {
+        BuildNode::Leaf { bbox, start, count } => nodes.push(FlatNode {
+            bbox: *bbox,
+            offset: *start as u32,
+            count: *count as u16,
+            axis: 0,
+        }),
+        BuildNode::Inner {
+            bbox,
+            axis,
+            left,
+            right,
+        } => {
+            let index = nodes.len();
+            nodes.push(FlatNode {
+                bbox: *bbox,
+                offset: 0,
+                count: 0,
+                axis: *axis as u8,
+            });
+            flatten(left, nodes);
+            nodes[index].offset = nodes.len() as u32;
+            flatten(right, nodes);
+        }
+    }
+}
+
+/// `node` as a tree of [`BvhNode`]s. Leaves with a single object are the
+/// object itself.
+fn tree(node: &BuildNode, objects: &[Arc<dyn Hittable>]) -> Arc<dyn Hittable> {
+    match node {
+        BuildNode::Leaf {
+            start, count: 1, ..
+        } => objects[*start].clone(),
+        BuildNode::Leaf { bbox, start, count } => Arc::new(BvhLeaf {
+            objects: objects[*start..start + count].to_vec(),
+            box_0: *bbox,
+        }),
+        BuildNode::Inner {
+            bbox, left, right, ..
+        } => Arc::new(BvhNode {
+            left: tree(left, objects),
+            right: tree(right, objects),
+            box_0: *bbox,
+        }),
+    }
+}
+
+/// Orders `primitives`, whose clipped boxes are bounded by `bbox`, so that
+/// the first of the returned number go left, and returns that number with
+/// the split axis, or `None` to make a leaf.
+fn sah_split(
+    primitives: &mut [Primitive],
+    bbox: &Aabb,
+    bins: usize,
+    max_leaf_size: usize,
+) -> Option<(usize, usize)> {
+    let n = primitives.len();
+    if n == 1 {
+        return None;
+    }
+    let centroids = primitives[1..].iter().fold(
+        Aabb::new(&primitives[0].centroid, &primitives[0].centroid),
+        |b, p| surrounding_box(b, Aabb::new(&p.centroid, &p.centroid)),
+    );
+    let bins = bins.max(2);
+    let bin_of = |p: &Primitive, axis: usize| {
+        let extent = centroids.maximum[axis] - centroids.minimum[axis];
+        let offset = (p.centroid[axis] - centroids.minimum[axis]) / extent;
+        ((offset * bins as f64) as usize).min(bins - 1)
+    };
+
+    // (cost, axis, bins on the left)
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroids.maximum[axis] - centroids.minimum[axis] <= 0.0 {
+            continue;
+        }
+        let mut counts = vec![Bin::default(); bins];
+        for p in primitives.iter() {
+            let bin = &mut counts[bin_of(p, axis)];
+            bin.count += 1;
+            bin.bbox = Some(
+                bin.bbox
+                    .map_or(p.clipped, |b| surrounding_box(b, p.clipped)),
+            );
+        }
+        // Area times count of everything right of each border, swept from
+        // the right, then the left side swept from the left.
+        let mut right_cost = vec![0.0; bins];
+        let mut acc = Bin::default();
+        for i in (1..bins).rev() {
+            acc = merge(acc, counts[i]);
+            right_cost[i] = acc.bbox.map_or(0.0, |b| b.surface_area()) * acc.count as f64;
+        }
+        let mut acc = Bin::default();
+        for i in 1..bins {
+            acc = merge(acc, counts[i - 1]);
+            let cost =
+                acc.bbox.map_or(0.0, |b| b.surface_area()) * acc.count as f64 + right_cost[i];
+            if acc.count > 0 && acc.count < n && best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    let area = bbox.surface_area();
+    let leaf_cost = area * n as f64;
+    match best {
+        Some((cost, axis, left_bins))
+            if n > max_leaf_size || area * TRAVERSAL_COST + cost < leaf_cost =>
+        {
+            let mut mid = 0;
+            for i in 0..n {
+                if bin_of(&primitives[i], axis) < left_bins {
+                    primitives.swap(i, mid);
+                    mid += 1;
+                }
             }
+            Some((mid, axis))
         }
+        // All centroids coincide, so no plane separates the objects.
+        None if n > max_leaf_size => Some((n / 2, 0)),
+        _ => None,
+    }
+}
+
+fn merge(a: Bin, b: Bin) -> Bin {
+    Bin {
+        count: a.count + b.count,
+        bbox: match (a.bbox, b.bbox) {
+            (Some(x), Some(y)) => Some(surrounding_box(x, y)),
+            (x, y) => x.or(y),
+        },
     }
 }
 
 impl Hittable for BvhNode {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count_visits(1);
         if self.box_0.hit(ray, t_min, t_max) {
             if let Some(hit_left) = self.left.hit(ray, t_min, t_max) {
                 return if let Some(hit_right) = self.right.hit(ray, t_min, hit_left.t) {
@@ -93,4 +618,343 @@ impl Hittable for BvhNode {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(self.box_0)
     }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.left.materials(visit);
+        self.right.materials(visit);
+    }
+}
+
+impl Hittable for BvhTree {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.root.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.root.bounding_box(time0, time1)
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        Some(self.stats)
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.root.materials(visit);
+    }
+}
+
+impl Hittable for BvhLeaf {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count_visits(1);
+        if !self.box_0.hit(ray, t_min, t_max) {
+            return None;
+        }
+        let mut rec = None;
+        let mut closest_so_far = t_max;
+        for object in &self.objects {
+            if let Some(hit) = object.hit(ray, t_min, closest_so_far) {
+                closest_so_far = hit.t;
+                rec = Some(hit);
+            }
+        }
+        rec
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(self.box_0)
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        for object in &self.objects {
+            object.materials(visit);
+        }
+    }
+}
+
+impl Hittable for FlatBvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let inv_dir = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let dir_is_negative = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+        let mut rec = None;
+        let mut closest_so_far = t_max;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut index = 0;
+        let mut visited = 0;
+        loop {
+            visited += 1;
+            let node = &self.nodes[index];
+            if node.bbox.hit_inverse(ray, &inv_dir, t_min, closest_so_far) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    for object in &self.objects[start..start + node.count as usize] {
+                        if let Some(hit) = object.hit(ray, t_min, closest_so_far) {
+                            closest_so_far = hit.t;
+                            rec = Some(hit);
+                        }
+                    }
+                } else {
+                    // Visit the child on the near side first, so that its
+                    // hits cut the far one short.
+                    let (near, far) = if dir_is_negative[node.axis as usize] {
+                        (node.offset, index as u32 + 1)
+                    } else {
+                        (index as u32 + 1, node.offset)
+                    };
+                    stack[stack_size] = far;
+                    stack_size += 1;
+                    index = near as usize;
+                    continue;
+                }
+            }
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            index = stack[stack_size] as usize;
+        }
+        count_visits(visited);
+        rec
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(self.nodes[0].bbox)
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        Some(self.stats)
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        for object in &self.objects {
+            object.materials(visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::Instance;
+    use crate::mat4::Mat4;
+    use crate::material::Lambertian;
+    use crate::rtweekend::{random_f64_range, seed_rng};
+    use crate::scene::Scene;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+
+    /// A plane without bounds in x and z, which no ray hits.
+    struct Floor;
+
+    impl Hittable for Floor {
+        fn hit(&self, _ray: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord> {
+            None
+        }
+
+        fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+            Some(Aabb::new(
+                &Point3::new(f64::NEG_INFINITY, -0.0001, f64::NEG_INFINITY),
+                &Point3::new(f64::INFINITY, 0.0001, f64::INFINITY),
+            ))
+        }
+    }
+
+    fn stats(objects: &[Arc<dyn Hittable>], split: BvhSplit) -> BvhStats {
+        seed_rng(1);
+        let settings = BvhSettings {
+            split,
+            ..BvhSettings::default()
+        };
+        BvhNode::build(objects.to_vec(), 0.0, 1.0, &settings).1
+    }
+
+    #[test]
+    fn sah_cost_is_finite_and_no_worse_than_median() {
+        for name in ["cornell_box", "cornell_smoke", "random_scene"] {
+            seed_rng(1);
+            let objects = Scene::by_name(name).unwrap().world.objects;
+            let sah = stats(&objects, BvhSplit::Sah);
+            let median = stats(&objects, BvhSplit::Median);
+            assert!(sah.sah_cost.is_finite(), "{}: {}", name, sah);
+            assert!(
+                sah.sah_cost <= median.sah_cost,
+                "{}: {} > {}",
+                name,
+                sah,
+                median
+            );
+        }
+    }
+
+    #[test]
+    fn flat_layout_finds_the_same_hits_as_the_tree() {
+        seed_rng(7);
+        let material = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        let objects: Vec<Arc<dyn Hittable>> = (0..200)
+            .map(|_| {
+                let center = Point3::new(
+                    random_f64_range(-10.0, 10.0),
+                    random_f64_range(-10.0, 10.0),
+                    random_f64_range(-10.0, 10.0),
+                );
+                let sphere = Sphere::new(center, random_f64_range(0.1, 1.0), material.clone());
+                Arc::new(sphere) as Arc<dyn Hittable>
+            })
+            .collect();
+        let list = HittableList {
+            objects: objects.clone(),
+        };
+        for split in [BvhSplit::Sah, BvhSplit::Median] {
+            let build = |layout| {
+                seed_rng(1);
+                let settings = BvhSettings {
+                    split,
+                    layout,
+                    ..BvhSettings::default()
+                };
+                BvhNode::build(objects.clone(), 0.0, 1.0, &settings).0
+            };
+            let (flat, tree) = (build(BvhLayout::Flat), build(BvhLayout::Tree));
+            let mut hits = 0;
+            for _ in 0..1000 {
+                let origin = Point3::new(
+                    random_f64_range(-15.0, 15.0),
+                    random_f64_range(-15.0, 15.0),
+                    random_f64_range(-15.0, 15.0),
+                );
+                let dir = Vec3::new(
+                    random_f64_range(-1.0, 1.0),
+                    random_f64_range(-1.0, 1.0),
+                    random_f64_range(-1.0, 1.0),
+                );
+                let ray = Ray::new(&origin, &dir, 0.0);
+                let hit = |h: &dyn Hittable| {
+                    h.hit(&ray, 0.001, f64::INFINITY)
+                        .map(|rec| (rec.t, rec.normal))
+                };
+                let expected = hit(&list);
+                assert_eq!(hit(flat.as_ref()), expected);
+                assert_eq!(hit(tree.as_ref()), expected);
+                hits += expected.is_some() as usize;
+            }
+            assert!(hits > 100, "only {} rays hit", hits);
+        }
+    }
+
+    #[test]
+    fn unbounded_objects_keep_sah_cost_finite() {
+        let material = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        let mut objects: Vec<Arc<dyn Hittable>> = vec![Arc::new(Floor)];
+        for i in 0..8 {
+            let center = Point3::new(i as f64 * 3.0, 1.0, 0.0);
+            objects.push(Arc::new(Sphere::new(center, 1.0, material.clone())));
+        }
+        let settings = BvhSettings::default();
+        let (bvh, stats) = BvhNode::build(objects, 0.0, 1.0, &settings);
+        assert!(stats.sah_cost.is_finite(), "{}", stats);
+        let ray = Ray::new(
+            &Point3::new(9.0, 1.0, 10.0),
+            &Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let rec = bvh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 9.0).abs() < 1e-9);
+    }
+
+    /// The plane y = 0, which has no bounding box.
+    struct Ground(Arc<dyn Material>);
+
+    impl Hittable for Ground {
+        fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            let t = -ray.orig.y / ray.dir.y;
+            if !(t_min..=t_max).contains(&t) {
+                return None;
+            }
+            let normal = Vec3::new(0.0, 1.0, 0.0);
+            let mut rec = HitRecord::new(ray.at(t), normal, self.0.clone(), t);
+            rec.set_face_normal(ray, &normal);
+            Some(rec)
+        }
+
+        fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+            None
+        }
+    }
+
+    #[test]
+    fn objects_without_bounds_are_tested_next_to_the_hierarchy() {
+        let material: Arc<dyn Material> =
+            Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        let objects: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Sphere::new(
+                Point3::new(0.0, 2.0, 0.0),
+                1.0,
+                material.clone(),
+            )),
+            Arc::new(Ground(material.clone())),
+            Arc::new(Sphere::new(
+                Point3::new(5.0, 2.0, 0.0),
+                1.0,
+                material.clone(),
+            )),
+        ];
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        for layout in [BvhLayout::Flat, BvhLayout::Tree] {
+            let settings = BvhSettings {
+                layout,
+                ..BvhSettings::default()
+            };
+            let (bvh, stats) = BvhNode::build(objects.clone(), 0.0, 1.0, &settings);
+            assert_eq!(stats.objects, 3);
+            assert!(
+                stats.sah_cost.is_finite() && stats.sah_cost > 1.0,
+                "{}",
+                stats
+            );
+            let t = |x| {
+                let ray = Ray::new(&Point3::new(x, 10.0, 0.0), &down, 0.0);
+                bvh.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t)
+            };
+            assert_eq!(t(0.0), Some(7.0));
+            assert_eq!(t(5.0), Some(7.0));
+            assert_eq!(t(2.5), Some(10.0));
+        }
+    }
+
+    #[test]
+    fn no_objects_give_an_empty_hierarchy() {
+        let (bvh, stats) = BvhNode::build(Vec::new(), 0.0, 1.0, &BvhSettings::default());
+        assert_eq!((stats.objects, stats.nodes, stats.leaves), (0, 0, 0));
+        let ray = Ray::new(&Point3::zero(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(bvh.hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn stats_count_nested_hierarchies() {
+        seed_rng(3);
+        let material = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        let spheres: Vec<Arc<dyn Hittable>> = (0..10)
+            .map(|i| {
+                let center = Point3::new(i as f64 * 3.0, 0.0, 0.0);
+                Arc::new(Sphere::new(center, 1.0, material.clone())) as Arc<dyn Hittable>
+            })
+            .collect();
+        let group = BvhNode::new_vec(spheres, 0.0, 1.0);
+        let nested = group.bvh_stats().unwrap();
+        assert_eq!(nested.objects, 10);
+        let mut objects: Vec<Arc<dyn Hittable>> = Vec::new();
+        for z in [10.0, 20.0] {
+            let matrix = Mat4::translate(Vec3::new(0.0, 0.0, z));
+            objects.push(Arc::new(
+                Instance::new(group.clone(), matrix, None).unwrap(),
+            ));
+        }
+        objects.push(Arc::new(Sphere::new(Point3::zero(), 1.0, material)));
+        let (_, stats) = BvhNode::build(objects, 0.0, 1.0, &BvhSettings::default());
+        assert_eq!(stats.objects, 21);
+        assert!(stats.nodes >= 2 * nested.nodes, "{}", stats);
+        assert!(stats.leaves > 2 * nested.leaves, "{}", stats);
+        assert!(stats.depth > nested.depth, "{}", stats);
+        assert!(stats.sah_cost.is_finite(), "{}", stats);
+    }
 }
@@ -1,70 +1,543 @@
 use crate::ray::Ray;
-use crate::rtweekend::{degrees_to_radians, random_f64_range};
+use crate::rtweekend::degrees_to_radians;
+use crate::sampler::{sample_unit_disk, Sampler};
 use crate::vec3::{Point3, Vec3};
-use std::ops::Mul;
+use std::f64::consts::PI;
+use std::ops::{Mul, Sub};
+use std::sync::Arc;
 
-#[derive(Clone)]
-pub struct Camera {
+/// Width of a full-frame 35mm sensor, which relates field of view, focal
+/// length and f-stop unless a different sensor is given.
+const FULL_FRAME_WIDTH: f64 = 36.0;
+
+/// Turns a position on the image into a camera ray.
+pub trait CameraModel: Send + Sync {
+    /// The ray through image position (`s`, `t`), both in `[0, 1]` from the
+    /// bottom left, drawing lens position and time from `sampler`. `None` for
+    /// positions the camera does not see, such as the corners of a circular
+    /// fisheye image.
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray>;
+}
+
+/// How directions are mapped onto the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    /// Straight lines stay straight; the thin lens gives depth of field.
+    Perspective,
+    /// Parallel rays along the view direction, covering the area the
+    /// perspective view covers at the focus distance.
+    Orthographic,
+    /// Distance from the image center is proportional to the angle from the
+    /// view direction.
+    FisheyeEquidistant,
+    /// Equal solid angles cover equal image areas.
+    FisheyeEquisolid,
+    /// The full sphere, longitude across and latitude up the image, for 2:1
+    /// environment maps. Ignores the field of view.
+    Equirectangular,
+    /// Angle around the vertical axis across the image and height on a
+    /// cylinder up it.
+    Cylindrical,
+}
+
+impl Projection {
+    pub const NAMES: [&'static str; 6] = [
+        "perspective",
+        "orthographic",
+        "fisheye_equidistant",
+        "fisheye_equisolid",
+        "equirectangular",
+        "cylindrical",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "perspective" => Some(Self::Perspective),
+            "orthographic" => Some(Self::Orthographic),
+            "fisheye_equidistant" => Some(Self::FisheyeEquidistant),
+            "fisheye_equisolid" => Some(Self::FisheyeEquisolid),
+            "equirectangular" => Some(Self::Equirectangular),
+            "cylindrical" => Some(Self::Cylindrical),
+            _ => None,
+        }
+    }
+}
+
+/// How wide the camera sees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldOfView {
+    /// Angle between the top and bottom edge of the image, in degrees.
+    Vertical(f64),
+    /// Angle between the left and right edge of the image, in degrees.
+    Horizontal(f64),
+    /// Focal length in millimetres, on a sensor of
+    /// [`CameraConfig::sensor_width`].
+    FocalLength(f64),
+}
+
+/// Size of the lens opening, which sets the depth of field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aperture {
+    /// Diameter in scene units; zero for a pinhole camera.
+    Diameter(f64),
+    /// Focal length divided by the diameter, as on a real lens.
+    FStop(f64),
+}
+
+/// Distance at which the image is sharp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Focus {
+    /// Focus on the point the camera looks at.
+    LookAt,
+    /// Distance from the camera in scene units.
+    Distance(f64),
+}
+
+/// Placement, projection and lens of a camera, built up with chained setters:
+///
+/// ```
+/// use raytracer::camera::CameraConfig;
+/// use raytracer::Point3;
+///
+/// let camera = CameraConfig::new(Point3::new(13.0, 2.0, 3.0), Point3::zero())
+///     .focal_length(50.0)
+///     .f_stop(2.8)
+///     .roll(10.0)
+///     .build(16.0 / 9.0);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraConfig {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    /// Direction that is up in the image, before `roll`.
+    pub vup: Vec3,
+    pub projection: Projection,
+    /// Rotation about the view direction in degrees, counterclockwise as seen
+    /// by the camera.
+    pub roll: f64,
+    pub fov: FieldOfView,
+    /// Sensor width in millimetres.
+    pub sensor_width: f64,
+    pub aperture: Aperture,
+    pub focus: Focus,
+    /// Length of one scene unit in millimetres, to turn f-stops into lens
+    /// sizes in the scene.
+    pub unit_mm: f64,
+    /// Shutter interval for motion blur.
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl CameraConfig {
+    /// A pinhole camera at `lookfrom` looking at `lookat` with a 40 degree
+    /// vertical field of view, y up and the shutter open from 0 to 1.
+    pub fn new(lookfrom: Point3, lookat: Point3) -> Self {
+        Self {
+            lookfrom,
+            lookat,
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            projection: Projection::Perspective,
+            roll: 0.0,
+            fov: FieldOfView::Vertical(40.0),
+            sensor_width: FULL_FRAME_WIDTH,
+            aperture: Aperture::Diameter(0.0),
+            focus: Focus::LookAt,
+            unit_mm: 1000.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+
+    pub fn vup(mut self, vup: Vec3) -> Self {
+        self.vup = vup;
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    pub fn roll(mut self, degrees: f64) -> Self {
+        self.roll = degrees;
+        self
+    }
+
+    pub fn vfov(mut self, degrees: f64) -> Self {
+        self.fov = FieldOfView::Vertical(degrees);
+        self
+    }
+
+    pub fn hfov(mut self, degrees: f64) -> Self {
+        self.fov = FieldOfView::Horizontal(degrees);
+        self
+    }
+
+    pub fn focal_length(mut self, mm: f64) -> Self {
+        self.fov = FieldOfView::FocalLength(mm);
+        self
+    }
+
+    pub fn sensor_width(mut self, mm: f64) -> Self {
+        self.sensor_width = mm;
+        self
+    }
+
+    pub fn aperture(mut self, diameter: f64) -> Self {
+        self.aperture = Aperture::Diameter(diameter);
+        self
+    }
+
+    pub fn f_stop(mut self, f_number: f64) -> Self {
+        self.aperture = Aperture::FStop(f_number);
+        self
+    }
+
+    pub fn focus_distance(mut self, distance: f64) -> Self {
+        self.focus = Focus::Distance(distance);
+        self
+    }
+
+    pub fn autofocus(mut self) -> Self {
+        self.focus = Focus::LookAt;
+        self
+    }
+
+    pub fn unit_mm(mut self, mm: f64) -> Self {
+        self.unit_mm = mm;
+        self
+    }
+
+    pub fn shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
+    /// Focal length in units of half the image width, for a lens that puts
+    /// rays at angle θ from the view direction at `focal * mapping(θ)` from
+    /// the image center.
+    fn focal(&self, aspect_ratio: f64, mapping: fn(f64) -> f64) -> f64 {
+        match self.fov {
+            FieldOfView::Vertical(degrees) => {
+                1.0 / aspect_ratio / mapping(degrees_to_radians(degrees) / 2.0)
+            }
+            FieldOfView::Horizontal(degrees) => 1.0 / mapping(degrees_to_radians(degrees) / 2.0),
+            FieldOfView::FocalLength(mm) => 2.0 * mm / self.sensor_width,
+        }
+    }
+
+    /// Lens diameter in scene units.
+    fn lens_diameter(&self, focal: f64) -> f64 {
+        match self.aperture {
+            Aperture::Diameter(diameter) => diameter,
+            Aperture::FStop(f_number) => {
+                let focal_length = focal * self.sensor_width / 2.0;
+                focal_length / f_number / self.unit_mm
+            }
+        }
+    }
+
+    fn focus_dist(&self) -> f64 {
+        match self.focus {
+            Focus::LookAt => (self.lookat - self.lookfrom).length(),
+            Focus::Distance(distance) => distance,
+        }
+    }
+
+    /// Camera axes: right, up and backwards.
+    fn frame(&self) -> Frame {
+        let w = (self.lookfrom - self.lookat).unit_vector();
+        let u0 = self.vup.cross(w).unit_vector();
+        let v0 = w.cross(u0);
+        let (sin, cos) = degrees_to_radians(self.roll).sin_cos();
+        Frame {
+            origin: self.lookfrom,
+            u: u0.mul(cos) + v0.mul(sin),
+            v: v0.mul(cos) - u0.mul(sin),
+            w,
+            time0: self.time0,
+            time1: self.time1,
+        }
+    }
+
+    pub fn build(&self, aspect_ratio: f64) -> Arc<dyn CameraModel> {
+        let frame = self.frame();
+        let perspective_focal = self.focal(aspect_ratio, f64::tan);
+        match self.projection {
+            Projection::Perspective => {
+                let focus_dist = self.focus_dist();
+                let viewport_width = 2.0 / perspective_focal;
+                let viewport_height = viewport_width / aspect_ratio;
+                let horizontal = frame.u.mul(viewport_width).mul(focus_dist);
+                let vertical = frame.v.mul(viewport_height).mul(focus_dist);
+                Arc::new(ThinLensCamera {
+                    lower_left_corner: frame.origin
+                        - horizontal / 2.0
+                        - vertical / 2.0
+                        - frame.w.mul(focus_dist),
+                    horizontal,
+                    vertical,
+                    lens_radius: self.lens_diameter(perspective_focal) / 2.0,
+                    frame,
+                })
+            }
+            Projection::Orthographic => {
+                let half_width = self.focus_dist() / perspective_focal;
+                Arc::new(OrthographicCamera {
+                    frame,
+                    half_width,
+                    half_height: half_width / aspect_ratio,
+                })
+            }
+            Projection::FisheyeEquidistant => Arc::new(FisheyeCamera {
+                frame,
+                focal: self.focal(aspect_ratio, |theta| theta),
+                aspect_ratio,
+                equisolid: false,
+            }),
+            Projection::FisheyeEquisolid => Arc::new(FisheyeCamera {
+                frame,
+                focal: self.focal(aspect_ratio, |theta| 2.0 * (theta / 2.0).sin()),
+                aspect_ratio,
+                equisolid: true,
+            }),
+            Projection::Equirectangular => Arc::new(EquirectangularCamera { frame }),
+            Projection::Cylindrical => {
+                // The angle across is linear in image position, the height up
+                // the cylinder is not.
+                let focal = match self.fov {
+                    FieldOfView::Vertical(_) => perspective_focal,
+                    _ => self.focal(aspect_ratio, |theta| theta),
+                };
+                Arc::new(CylindricalCamera {
+                    frame,
+                    focal,
+                    aspect_ratio,
+                })
+            }
+        }
+    }
+}
+
+/// Position, axes and shutter interval shared by all camera models.
+#[derive(Clone, Copy, Debug)]
+struct Frame {
     origin: Point3,
-    lower_left_corner: Point3,
-    horizontal: Vec3,
-    vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    //w: Vec3,
-    lens_radius: f64,
+    w: Vec3,
     time0: f64,
     time1: f64,
 }
-#[allow(clippy::too_many_arguments)]
-impl Camera {
-    pub fn new(
-        lookfrom: Point3,
-        lookat: Point3,
-        vup: Vec3,
-        vfov: f64,
-        aspect_ratio: f64,
-        aperture: f64,
-        focus_dist: f64,
-        time0: f64,
-        time1: f64,
-    ) -> Self {
-        let theta = degrees_to_radians(vfov);
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
-        let w0 = (lookfrom - lookat).unit_vector();
-        let u0 = vup.cross(w0).unit_vector();
-        let v0 = w0.cross(u0);
-        let horizontal0 = u0.mul(viewport_width).mul(focus_dist);
-        let vertical0 = v0.mul(viewport_height).mul(focus_dist);
-        let lower_left_corner0 =
-            lookfrom - horizontal0 / 2.0 - vertical0 / 2.0 - w0.mul(focus_dist);
-        Self {
-            //w: w0,
-            u: u0,
-            v: v0,
-            origin: lookfrom,
-            horizontal: horizontal0,
-            vertical: vertical0,
-            lower_left_corner: lower_left_corner0,
-            lens_radius: aperture / 2.0,
-            time0,
-            time1,
-            // time0: 0.0,
-            // time1: 1.0,
-        }
-    }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = Vec3::random_in_unit_disk().mul(self.lens_radius);
-        let offset = self.u.mul(rd.x) + self.v.mul(rd.y);
+impl Frame {
+    fn ray(&self, origin: Point3, dir: Vec3, sampler: &mut dyn Sampler) -> Ray {
         Ray::new(
-            &(self.origin + offset),
-            &(self.lower_left_corner + self.horizontal.mul(s) + self.vertical.mul(t)
-                - self.origin
-                - offset),
-            random_f64_range(self.time0, self.time1),
+            &origin,
+            &dir,
+            self.time0 + (self.time1 - self.time0) * sampler.get_1d(),
+        )
+    }
+
+    /// Converts a direction from camera coordinates, with -z forward.
+    fn local(&self, x: f64, y: f64, z: f64) -> Vec3 {
+        self.u.mul(x) + self.v.mul(y) + self.w.mul(z)
+    }
+}
+
+/// Thin-lens perspective camera with a shutter interval for motion blur.
+pub struct ThinLensCamera {
+    frame: Frame,
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    lens_radius: f64,
+}
+
+impl CameraModel for ThinLensCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let rd = sample_unit_disk(sampler.get_2d()).mul(self.lens_radius);
+        let offset = self.frame.u.mul(rd.x) + self.frame.v.mul(rd.y);
+        let target = self.lower_left_corner + self.horizontal.mul(s) + self.vertical.mul(t);
+        let dir = target - self.frame.origin - offset;
+        Some(self.frame.ray(self.frame.origin + offset, dir, sampler))
+    }
+}
+
+pub struct OrthographicCamera {
+    frame: Frame,
+    half_width: f64,
+    half_height: f64,
+}
+
+impl CameraModel for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let origin = self.frame.origin
+            + self.frame.u.mul((2.0 * s - 1.0) * self.half_width)
+            + self.frame.v.mul((2.0 * t - 1.0) * self.half_height);
+        Some(
+            self.frame
+                .ray(origin, Vec3::zero().sub(self.frame.w), sampler),
         )
     }
 }
+
+/// Circular fisheye; positions outside the image circle see nothing.
+pub struct FisheyeCamera {
+    frame: Frame,
+    focal: f64,
+    aspect_ratio: f64,
+    equisolid: bool,
+}
+
+impl CameraModel for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let x = 2.0 * s - 1.0;
+        let y = (2.0 * t - 1.0) / self.aspect_ratio;
+        let radius = (x * x + y * y).sqrt();
+        let r = radius / self.focal;
+        let theta = if self.equisolid {
+            if r > 2.0 {
+                return None;
+            }
+            2.0 * (r / 2.0).asin()
+        } else {
+            r
+        };
+        if theta > PI {
+            return None;
+        }
+        let (sin, cos) = theta.sin_cos();
+        let scale = if radius > 0.0 { sin / radius } else { 0.0 };
+        let dir = self.frame.local(x * scale, y * scale, -cos);
+        Some(self.frame.ray(self.frame.origin, dir, sampler))
+    }
+}
+
+/// Longitude from -180 to 180 degrees across the image, latitude from -90
+/// to 90 degrees up it, with the view direction in the center.
+pub struct EquirectangularCamera {
+    frame: Frame,
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let phi = 2.0 * PI * (s - 0.5);
+        let lambda = PI * (t - 0.5);
+        let dir = self.frame.local(
+            lambda.cos() * phi.sin(),
+            lambda.sin(),
+            -lambda.cos() * phi.cos(),
+        );
+        Some(self.frame.ray(self.frame.origin, dir, sampler))
+    }
+}
+
+/// Projection onto a vertical cylinder around the camera.
+pub struct CylindricalCamera {
+    frame: Frame,
+    focal: f64,
+    aspect_ratio: f64,
+}
+
+impl CameraModel for CylindricalCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let phi = (2.0 * s - 1.0) / self.focal;
+        let height = (2.0 * t - 1.0) / self.aspect_ratio / self.focal;
+        let dir = self.frame.local(phi.sin(), height, -phi.cos());
+        Some(self.frame.ray(self.frame.origin, dir, sampler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerKind;
+
+    fn config() -> CameraConfig {
+        CameraConfig::new(Point3::new(3.0, 2.0, -5.0), Point3::new(-1.0, 0.5, 2.0))
+    }
+
+    /// Distance of `point` from the line along `ray`.
+    fn distance_to(ray: &Ray, point: Point3) -> f64 {
+        let to_point = point - ray.orig;
+        to_point.cross(ray.dir).length() / ray.dir.length()
+    }
+
+    #[test]
+    fn center_looks_at_lookat() {
+        let mut sampler = SamplerKind::Independent.build(1, 1);
+        for name in Projection::NAMES {
+            let projection = Projection::from_name(name).unwrap();
+            for config in [
+                config(),
+                config().roll(30.0).vup(Vec3::new(1.0, 1.0, 0.0)),
+                config().focal_length(85.0).f_stop(1.4).unit_mm(10.0),
+            ] {
+                let camera = config.projection(projection).build(1.5);
+                for _ in 0..16 {
+                    let ray = camera.get_ray(0.5, 0.5, sampler.as_mut()).unwrap();
+                    assert!(distance_to(&ray, config.lookat) < 1e-9, "{}", name);
+                    let forward = config.lookat - config.lookfrom;
+                    assert!(ray.dir.dot(forward) > 0.0, "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn field_of_view_and_focal_length_agree() {
+        // A 50mm lens on a 36x24mm sensor.
+        let aspect_ratio = 1.5;
+        let vfov = 2.0 * (12.0f64 / 50.0).atan().to_degrees();
+        let hfov = 2.0 * (18.0f64 / 50.0).atan().to_degrees();
+        let focal = |config: CameraConfig| config.focal(aspect_ratio, f64::tan);
+        let expected = focal(config().focal_length(50.0));
+        assert!((expected - 2.0 * 50.0 / 36.0).abs() < 1e-12);
+        assert!((focal(config().vfov(vfov)) - expected).abs() < 1e-12);
+        assert!((focal(config().hfov(hfov)) - expected).abs() < 1e-12);
+        // A smaller sensor narrows the view of the same lens.
+        assert!(focal(config().focal_length(50.0).sensor_width(24.0)) > expected);
+
+        // The top edge of the image is half the vertical field of view off
+        // the view direction.
+        let mut sampler = SamplerKind::Independent.build(1, 1);
+        let config = config().focal_length(50.0);
+        let camera = config.build(aspect_ratio);
+        let forward = (config.lookat - config.lookfrom).unit_vector();
+        for (s, t, fov) in [(0.5, 1.0, vfov), (0.5, 0.0, vfov), (1.0, 0.5, hfov)] {
+            let ray = camera.get_ray(s, t, sampler.as_mut()).unwrap();
+            let angle = ray.dir.unit_vector().dot(forward).acos().to_degrees();
+            assert!(
+                (angle - fov / 2.0).abs() < 1e-9,
+                "{} vs {}",
+                angle,
+                fov / 2.0
+            );
+        }
+    }
+
+    #[test]
+    fn f_stops_set_the_lens_diameter() {
+        // 50mm at f/2 opens 25mm, which is 0.025 scene units of a metre.
+        let config = config().focal_length(50.0).f_stop(2.0);
+        let focal = config.focal(1.5, f64::tan);
+        assert!((config.lens_diameter(focal) - 0.025).abs() < 1e-12);
+        let centimetres = config.unit_mm(10.0);
+        assert!((centimetres.lens_diameter(focal) - 2.5).abs() < 1e-12);
+        let explicit = config.aperture(0.3);
+        assert_eq!(explicit.lens_diameter(focal), 0.3);
+
+        // Rays leave from anywhere on the lens, but not beyond it.
+        let mut sampler = SamplerKind::Independent.build(1, 1);
+        let camera = config.build(1.5);
+        let spread = (0..256)
+            .map(|_| {
+                let ray = camera.get_ray(0.3, 0.6, sampler.as_mut()).unwrap();
+                (ray.orig - config.lookfrom).length()
+            })
+            .fold(0.0, f64::max);
+        assert!(spread <= 0.0125 + 1e-12 && spread > 0.01, "{}", spread);
+    }
+}
@@ -0,0 +1,508 @@
+use raytracer::aov::Aov;
+use raytracer::bvh::{BvhLayout, BvhSettings, BvhSplit};
+use raytracer::camera::Projection;
+use raytracer::denoise::DenoiseSettings;
+use raytracer::film::{Filter, FilterKind};
+use raytracer::integrator::IntegratorKind;
+use raytracer::output::{OutputFormat, OutputSettings};
+use raytracer::rtweekend::parse_aspect_ratio;
+use raytracer::sampler::SamplerKind;
+use raytracer::scene::SCENE_NAMES;
+use raytracer::tonemap::{ToneMap, ToneMapping, Transfer};
+use std::path::Path;
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS]
+
+Options:
+  -s, --scene <NAME>      Built-in scene to render (default: final_scene)
+      --scene-file <PATH> Load the scene from a .scene description file
+  -w, --width <PIXELS>    Image width, overrides the scene default
+  -a, --aspect <RATIO>    Aspect ratio as a number or W:H, e.g. 16:9
+      --projection <NAME> perspective, orthographic, fisheye_equidistant,
+                          fisheye_equisolid, equirectangular or cylindrical
+                          (default: from the scene)
+  -n, --spp <N>           Samples per pixel, overrides the scene default; the
+                          upper bound with --adaptive
+      --adaptive <ERROR>  Stop sampling pixels whose relative error is below
+                          ERROR, e.g. 0.02
+      --min-spp <N>       Samples before a pixel may stop (default: 16)
+      --heatmap <PATH>    Also write the samples per pixel as an image
+      --aov <NAMES>       Also write these comma-separated passes next to the
+                          output, e.g. output/test.normal.exr: albedo, normal,
+                          depth, position, material_id or emission. They are
+                          stored linearly, so use EXR for values outside 0-1
+  -d, --depth <N>         Maximum ray bounce depth, 1-255 (default: 50)
+  -j, --threads <N>       Worker threads (default: available cores)
+      --tile-size <PIXELS> Edge length of the square render tiles (default: 16)
+  -o, --output <PATH>     Output image path (default: output/test.jpg)
+      --denoise           Filter the noise out of the image, guided by its
+                          albedo and normals
+      --denoise-strength <S> Noise levels to smooth over (default: 1); implies
+                          --denoise
+      --denoise-radius <PIXELS> Filter radius (default: 5); implies --denoise
+  -c, --checkpoint <N>    Write the image so far every N samples per pixel
+      --state <PATH>      Save the accumulated samples to PATH at every
+                          checkpoint and at the end
+      --resume            Continue the render saved in the --state file
+      --sampler <NAME>    independent, stratified, halton or sobol (default: sobol)
+      --bvh <SPLIT>       How the BVH splits: sah or median (default: sah)
+      --bvh-leaf-size <N> Most objects per BVH leaf (default: 4)
+      --bvh-layout <NAME> flat or tree (default: flat)
+      --bvh-stats         Print the node count, depth and SAH cost of the BVH
+      --filter <NAME>     Pixel filter: box, tent, gaussian, mitchell or lanczos
+                          (default: box)
+      --filter-radius <PIXELS> Filter radius (default: 0.5 for box, 1 for tent,
+                          1.5 for gaussian, 2 for mitchell and lanczos)
+  -i, --integrator <NAME> path, naive, direct, ao, normals, uv, depth,
+                          material_id or bvh_cost (default: path); bvh_cost
+                          needs a build with the bvh-cost feature
+      --ao-radius <R>     Occlusion distance for --integrator ao (default: unlimited)
+      --seed <N>          Seed for scene generation and sampling; the same seed
+                          and settings give the same image (default: random)
+  -f, --format <FORMAT>   jpeg, png, exr or hdr (default: from output extension)
+  -q, --quality <N>       JPEG quality, 1-100 (default: 60)
+  -t, --tonemap <OP>      clamp, reinhard, reinhard_extended, aces or uncharted2
+                          (default: clamp)
+  -e, --exposure <STOPS>  Exposure adjustment in stops (default: 0)
+      --white <VALUE>     Radiance mapped to display white (default: per operator)
+      --gamma <GAMMA>     Encode with a power curve instead of sRGB
+      --list-scenes       Print the available scene names and exit
+  -h, --help              Print this help and exit";
+
+#[derive(Clone, Debug)]
+pub enum SceneSource {
+    Builtin(String),
+    File(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub scene: SceneSource,
+    pub image_width: Option<usize>,
+    pub aspect_ratio: Option<f64>,
+    pub projection: Option<Projection>,
+    pub samples_per_pixel: Option<usize>,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples: Option<usize>,
+    pub heatmap: Option<String>,
+    pub aovs: Vec<Aov>,
+    pub denoise: Option<DenoiseSettings>,
+    pub max_depth: u8,
+    pub threads: usize,
+    pub tile_size: Option<usize>,
+    pub checkpoint_interval: Option<usize>,
+    pub state: Option<String>,
+    pub resume: bool,
+    pub seed: Option<u64>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub bvh: BvhSettings,
+    pub bvh_stats: bool,
+    pub integrator: IntegratorKind,
+    pub ao_radius: Option<f64>,
+    pub output: String,
+    pub output_settings: OutputSettings,
+}
+
+pub enum Command {
+    Render(Box<Args>),
+    ListScenes,
+    Help,
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut scene = SceneSource::Builtin(String::from("final_scene"));
+    let mut image_width = None;
+    let mut aspect_ratio = None;
+    let mut projection = None;
+    let mut samples_per_pixel = None;
+    let mut adaptive_threshold = None;
+    let mut min_samples = None;
+    let mut heatmap = None;
+    let mut aovs = Vec::new();
+    let mut denoise: Option<DenoiseSettings> = None;
+    let mut max_depth = 50;
+    let mut threads = None;
+    let mut tile_size = None;
+    let mut checkpoint_interval = None;
+    let mut state = None;
+    let mut resume = false;
+    let mut seed = None;
+    let mut sampler = SamplerKind::Sobol;
+    let mut filter_kind = FilterKind::Box;
+    let mut bvh = BvhSettings::default();
+    let mut bvh_stats = false;
+    let mut filter_radius = None;
+    let mut integrator = IntegratorKind::Path;
+    let mut ao_radius = None;
+    let mut output = String::from("output/test.jpg");
+    let mut format = None;
+    let mut quality = 60;
+    let mut tone_mapping = ToneMapping::default();
+
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or(format!("missing value for '{}'", flag))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list-scenes" => return Ok(Command::ListScenes),
+            "-s" | "--scene" => scene = SceneSource::Builtin(value()?),
+            "--scene-file" => scene = SceneSource::File(value()?),
+            "-w" | "--width" => image_width = Some(parse_positive(&flag, &value()?)?),
+            "-a" | "--aspect" => aspect_ratio = Some(parse_aspect(&value()?)?),
+            "--projection" => {
+                let v = value()?;
+                projection = Some(Projection::from_name(&v).ok_or(format!(
+                    "unknown projection '{}', expected one of: {}",
+                    v,
+                    Projection::NAMES.join(", ")
+                ))?);
+            }
+            "-n" | "--spp" => samples_per_pixel = Some(parse_positive(&flag, &value()?)?),
+            "--adaptive" => adaptive_threshold = Some(parse_positive_f64(&flag, &value()?)?),
+            "--min-spp" => min_samples = Some(parse_positive(&flag, &value()?)?),
+            "--heatmap" => heatmap = Some(value()?),
+            "--aov" => {
+                for name in value()?.split(',') {
+                    let aov = Aov::from_name(name.trim()).ok_or(format!(
+                        "unknown AOV '{}', expected one of: {}",
+                        name,
+                        Aov::NAMES.join(", ")
+                    ))?;
+                    if !aovs.contains(&aov) {
+                        aovs.push(aov);
+                    }
+                }
+            }
+            "-d" | "--depth" => {
+                let v = value()?;
+                max_depth = match v.parse::<u8>() {
+                    Ok(d) if d > 0 => d,
+                    _ => return Err(format!("depth must be in 1-255, got '{}'", v)),
+                };
+            }
+            "-j" | "--threads" => threads = Some(parse_positive(&flag, &value()?)?),
+            "--tile-size" => tile_size = Some(parse_positive(&flag, &value()?)?),
+            "-o" | "--output" => output = value()?,
+            "--denoise" => {
+                denoise.get_or_insert_with(DenoiseSettings::default);
+            }
+            "--denoise-strength" => {
+                denoise
+                    .get_or_insert_with(DenoiseSettings::default)
+                    .strength = parse_positive_f64(&flag, &value()?)?
+            }
+            "--denoise-radius" => {
+                denoise.get_or_insert_with(DenoiseSettings::default).radius =
+                    parse_positive(&flag, &value()?)?
+            }
+            "-c" | "--checkpoint" => checkpoint_interval = Some(parse_positive(&flag, &value()?)?),
+            "--state" => state = Some(value()?),
+            "--resume" => resume = true,
+            "--sampler" => {
+                let v = value()?;
+                sampler = SamplerKind::from_name(&v).ok_or(format!(
+                    "unknown sampler '{}', expected one of: {}",
+                    v,
+                    SamplerKind::NAMES.join(", ")
+                ))?;
+            }
+            "--bvh" => {
+                let v = value()?;
+                bvh.split = BvhSplit::from_name(&v).ok_or(format!(
+                    "unknown BVH split '{}', expected one of: {}",
+                    v,
+                    BvhSplit::NAMES.join(", ")
+                ))?;
+            }
+            "--bvh-layout" => {
+                let v = value()?;
+                bvh.layout = BvhLayout::from_name(&v).ok_or(format!(
+                    "unknown BVH layout '{}', expected one of: {}",
+                    v,
+                    BvhLayout::NAMES.join(", ")
+                ))?;
+            }
+            "--bvh-leaf-size" => bvh.max_leaf_size = parse_positive(&flag, &value()?)?,
+            "--bvh-stats" => bvh_stats = true,
+            "--filter" => {
+                let v = value()?;
+                filter_kind = FilterKind::from_name(&v).ok_or(format!(
+                    "unknown filter '{}', expected one of: {}",
+                    v,
+                    FilterKind::NAMES.join(", ")
+                ))?;
+            }
+            "--filter-radius" => filter_radius = Some(parse_positive_f64(&flag, &value()?)?),
+            "-i" | "--integrator" => {
+                let v = value()?;
+                integrator = IntegratorKind::from_name(&v).ok_or(format!(
+                    "unknown integrator '{}', expected one of: {}",
+                    v,
+                    IntegratorKind::NAMES.join(", ")
+                ))?;
+                if integrator == IntegratorKind::BvhCost && !cfg!(feature = "bvh-cost") {
+                    return Err(String::from(
+                        "the bvh_cost integrator needs a build with the bvh-cost feature",
+                    ));
+                }
+            }
+            "--ao-radius" => ao_radius = Some(parse_positive_f64(&flag, &value()?)?),
+            "--seed" => {
+                let v = value()?;
+                seed =
+                    Some(v.parse::<u64>().map_err(|_| {
+                        format!("seed must be a non-negative integer, got '{}'", v)
+                    })?);
+            }
+            "-f" | "--format" => {
+                let v = value()?;
+                format = Some(OutputFormat::from_name(&v).ok_or(format!(
+                    "unknown format '{}', expected jpeg, png, exr or hdr",
+                    v
+                ))?);
+            }
+            "-q" | "--quality" => {
+                let v = value()?;
+                quality = match v.parse::<u8>() {
+                    Ok(q) if (1..=100).contains(&q) => q,
+                    _ => return Err(format!("quality must be in 1-100, got '{}'", v)),
+                };
+            }
+            "-t" | "--tonemap" => {
+                let v = value()?;
+                tone_mapping.operator = ToneMap::from_name(&v).ok_or(format!(
+                    "unknown tone mapping operator '{}', expected one of: {}",
+                    v,
+                    ToneMap::NAMES.join(", ")
+                ))?;
+            }
+            "-e" | "--exposure" => {
+                let v = value()?;
+                tone_mapping.exposure = match v.parse::<f64>() {
+                    Ok(e) if e.is_finite() => e,
+                    _ => return Err(format!("exposure must be a number of stops, got '{}'", v)),
+                };
+            }
+            "--white" => tone_mapping.white_point = Some(parse_positive_f64(&flag, &value()?)?),
+            "--gamma" => {
+                tone_mapping.transfer = Transfer::Gamma(parse_positive_f64(&flag, &value()?)?)
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+
+    if let SceneSource::Builtin(name) = &scene {
+        if !SCENE_NAMES.contains(&name.as_str()) {
+            return Err(format!(
+                "unknown scene '{}', expected one of: {}",
+                name,
+                SCENE_NAMES.join(", ")
+            ));
+        }
+    }
+    if resume && state.is_none() {
+        return Err(String::from("'--resume' needs '--state <PATH>'"));
+    }
+    let format = match format {
+        Some(f) => f,
+        None => OutputFormat::from_path(Path::new(&output)).ok_or(format!(
+            "cannot infer format from '{}', use --format",
+            output
+        ))?,
+    };
+    if let Some(heatmap) = &heatmap {
+        if OutputFormat::from_path(Path::new(heatmap)).is_none() {
+            return Err(format!("cannot infer heatmap format from '{}'", heatmap));
+        }
+    }
+    let filter = Filter {
+        kind: filter_kind,
+        radius: filter_radius.unwrap_or(filter_kind.default_radius()),
+    };
+    let threads = match threads {
+        Some(t) => t,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    Ok(Command::Render(Box::new(Args {
+        scene,
+        image_width,
+        aspect_ratio,
+        projection,
+        samples_per_pixel,
+        adaptive_threshold,
+        min_samples,
+        heatmap,
+        aovs,
+        denoise,
+        max_depth,
+        threads,
+        tile_size,
+        checkpoint_interval,
+        state,
+        resume,
+        seed,
+        sampler,
+        filter,
+        bvh,
+        bvh_stats,
+        integrator,
+        ao_radius,
+        output,
+        output_settings: OutputSettings {
+            format,
+            quality,
+            tone_mapping,
+        },
+    })))
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "'{}' expects a positive integer, got '{}'",
+            flag, value
+        )),
+    }
+}
+
+fn parse_positive_f64(flag: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Ok(n),
+        _ => Err(format!(
+            "'{}' expects a positive number, got '{}'",
+            flag, value
+        )),
+    }
+}
+
+fn parse_aspect(value: &str) -> Result<f64, String> {
+    parse_aspect_ratio(value).ok_or(format!(
+        "aspect ratio must be a positive number or W:H, got '{}'",
+        value
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    fn render_args(args: &[&str]) -> Args {
+        match parse(args) {
+            Ok(Command::Render(args)) => *args,
+            Ok(_) => panic!("{:?} does not render", args),
+            Err(e) => panic!("{:?}: {}", args, e),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let args = render_args(&[]);
+        assert!(matches!(&args.scene, SceneSource::Builtin(name) if name == "final_scene"));
+        assert_eq!(args.image_width, None);
+        assert_eq!(args.aspect_ratio, None);
+        assert_eq!(args.samples_per_pixel, None);
+        assert_eq!(args.max_depth, 50);
+        assert!(args.threads > 0);
+        assert_eq!(args.sampler, SamplerKind::Sobol);
+        assert_eq!(args.filter, Filter::default());
+        assert_eq!(args.integrator, IntegratorKind::Path);
+        assert!(args.state.is_none() && !args.resume);
+        assert_eq!(args.output, "output/test.jpg");
+        assert_eq!(args.output_settings.format, OutputFormat::Jpeg);
+        assert_eq!(args.output_settings.quality, 60);
+        assert_eq!(args.output_settings.tone_mapping, ToneMapping::default());
+        assert!(matches!(parse(&["--help"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["--list-scenes"]), Ok(Command::ListScenes)));
+    }
+
+    #[test]
+    fn aspect_ratios_take_w_colon_h() {
+        let aspect = |value: &str| render_args(&["--aspect", value]).aspect_ratio.unwrap();
+        assert_eq!(aspect("16:9"), 16.0 / 9.0);
+        assert_eq!(aspect("2.35:1"), 2.35);
+        assert_eq!(aspect("1.5"), 1.5);
+        assert_eq!(render_args(&["-a", "4:3"]).aspect_ratio, Some(4.0 / 3.0));
+        assert_eq!(render_args(&["--aspect=4:3"]).aspect_ratio, Some(4.0 / 3.0));
+        for bad in ["16:0", "0:9", "-16:9", "16:", "wide", "0", "-1.5"] {
+            assert!(parse(&["--aspect", bad]).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn zero_and_negative_values_are_rejected() {
+        for (flag, value) in [
+            ("--width", "0"),
+            ("--width", "-640"),
+            ("--spp", "0"),
+            ("--spp", "-4"),
+            ("--threads", "0"),
+            ("--tile-size", "0"),
+            ("--depth", "0"),
+            ("--depth", "-1"),
+            ("--adaptive", "0"),
+            ("--adaptive", "-0.1"),
+            ("--filter-radius", "0"),
+            ("--white", "-1"),
+            ("--quality", "0"),
+            ("--seed", "-3"),
+        ] {
+            assert!(parse(&[flag, value]).is_err(), "{} {}", flag, value);
+        }
+        let args = render_args(&["-w", "640", "--spp=16", "-j", "2", "--depth", "8"]);
+        assert_eq!(args.image_width, Some(640));
+        assert_eq!(args.samples_per_pixel, Some(16));
+        assert_eq!(args.threads, 2);
+        assert_eq!(args.max_depth, 8);
+    }
+
+    #[test]
+    fn unknown_or_incomplete_arguments_are_rejected() {
+        let error = parse(&["--frobnicate"]).err().unwrap();
+        assert!(error.contains("--frobnicate"), "{}", error);
+        assert!(parse(&["scene.txt"]).is_err());
+        assert!(parse(&["--scene", "no_such_scene"]).is_err());
+        assert!(parse(&["--sampler", "random"]).is_err());
+        assert!(parse(&["--width"]).is_err());
+        assert!(parse(&["--resume"]).is_err());
+        assert!(parse(&["--resume", "--state", "render.state"]).is_ok());
+        assert_eq!(
+            parse(&["--integrator", "bvh_cost"]).is_ok(),
+            cfg!(feature = "bvh-cost")
+        );
+    }
+
+    #[test]
+    fn format_follows_the_output_extension() {
+        let format = |args: &[&str]| render_args(args).output_settings.format;
+        assert_eq!(format(&["-o", "out.png"]), OutputFormat::Png);
+        assert_eq!(format(&["-o", "renders/out.JPEG"]), OutputFormat::Jpeg);
+        assert_eq!(format(&["--output=out.exr"]), OutputFormat::Exr);
+        assert_eq!(format(&["-o", "out.hdr"]), OutputFormat::Hdr);
+        // An explicit format wins over the extension.
+        assert_eq!(format(&["-o", "out.img", "-f", "png"]), OutputFormat::Png);
+        assert_eq!(
+            format(&["-o", "out.png", "--format", "exr"]),
+            OutputFormat::Exr
+        );
+        assert!(parse(&["-o", "out.img"]).is_err());
+        assert!(parse(&["-o", "out"]).is_err());
+        assert!(parse(&["--heatmap", "samples"]).is_err());
+    }
+}
@@ -1,9 +1,10 @@
 use crate::aabb::Aabb;
+use crate::bvh::BvhStats;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::{Isotropic, Material};
 use crate::ray::Ray;
 use crate::rtweekend::random_f64;
-//use crate::texture::Texture;
+use crate::texture::Texture;
 use crate::vec3::Color;
 use crate::Vec3;
 use std::sync::Arc;
@@ -17,13 +18,13 @@ pub struct ConstantMedium {
 }
 
 impl ConstantMedium {
-    // pub fn new_texture(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         boundary: b,
-    //         phase_function: Arc::new(Isotropic::new_texture(a)),
-    //         neg_inv_density: -1.0 / d,
-    //     }
-    // }
+    pub fn new_texture(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
+        Self {
+            boundary: b,
+            phase_function: Arc::new(Isotropic::new_texture(a)),
+            neg_inv_density: -1.0 / d,
+        }
+    }
 
     pub fn new_color(b: Arc<dyn Hittable>, d: f64, c: Color) -> Self {
         Self {
@@ -76,4 +77,12 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.boundary.bounding_box(time0, time1)
     }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        self.boundary.bvh_stats()
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.phase_function);
+    }
 }
@@ -0,0 +1,201 @@
+//! Joint bilateral denoising of a rendered image.
+//!
+//! The filter averages each pixel with its neighbours, weighted by how close
+//! they are and how similar their albedo, normal and color are. Colors count
+//! as similar when they differ by no more than their noise, so the filter
+//! smooths noisy regions and keeps edges that the feature buffers or a
+//! converged image show. Only reflected light is filtered: emission seen
+//! directly is taken out before and added back after, and texture is kept by
+//! dividing the reflected light by the albedo and multiplying it back in
+//! afterwards.
+
+use crate::aov::Aov;
+use crate::framebuffer::Image;
+use crate::tonemap::luminance;
+use crate::vec3::Color;
+
+/// The AOVs [`denoise`] is guided by.
+pub const FEATURES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Emission];
+
+/// Smallest albedo divided out of a color, so that dark and emissive
+/// surfaces keep their color.
+const MIN_ALBEDO: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DenoiseSettings {
+    /// Pixels from the center to the edge of the filter window.
+    pub radius: usize,
+    /// How many standard errors two colors may differ by and still be
+    /// averaged; higher values smooth more.
+    pub strength: f64,
+    /// Albedo difference at which a neighbour's weight falls to about 60%.
+    pub albedo_sigma: f64,
+    /// Normal difference at which a neighbour's weight falls to about 60%.
+    pub normal_sigma: f64,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            radius: 5,
+            strength: 1.0,
+            albedo_sigma: 0.1,
+            normal_sigma: 0.2,
+        }
+    }
+}
+
+/// Filters `color`, whose pixels have the luminance variances `variance`,
+/// guided by the `albedo`, `normal` and `emission` AOVs of the same size.
+pub fn denoise(
+    color: &Image,
+    variance: &[f64],
+    albedo: &Image,
+    normal: &Image,
+    emission: &Image,
+    settings: &DenoiseSettings,
+) -> Image {
+    let (width, height) = (color.width, color.height);
+    let radius = settings.radius as isize;
+    let spatial = 1.0 / (2.0 * (settings.radius.max(1) as f64 / 2.0).powi(2));
+    let albedo_weight = 1.0 / (2.0 * settings.albedo_sigma * settings.albedo_sigma);
+    let normal_weight = 1.0 / (2.0 * settings.normal_sigma * settings.normal_sigma);
+    let strength_sq = settings.strength * settings.strength;
+
+    let irradiance: Vec<Color> = color
+        .pixels
+        .iter()
+        .zip(&emission.pixels)
+        .zip(&albedo.pixels)
+        .map(|((c, e), a)| demodulate(*c - *e, *a))
+        .collect();
+    let mut output = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let p = y * width + x;
+            let luminance_p = luminance(color.pixels[p]);
+            let mut sum = Color::zero();
+            let mut total_weight = 0.0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let (qx, qy) = (x as isize + dx, y as isize + dy);
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    let q = qy as usize * width + qx as usize;
+                    let mut distance = (dx * dx + dy * dy) as f64 * spatial;
+                    distance +=
+                        (albedo.pixels[p] - albedo.pixels[q]).length_squared() * albedo_weight;
+                    distance +=
+                        (normal.pixels[p] - normal.pixels[q]).length_squared() * normal_weight;
+                    let difference = luminance_p - luminance(color.pixels[q]);
+                    let noise = strength_sq * (variance[p] + variance[q]);
+                    if noise.is_finite() {
+                        // Differences within the noise are free.
+                        distance += (difference * difference / (noise + 1e-12) - 1.0).max(0.0);
+                    }
+                    let weight = (-distance).exp();
+                    sum += irradiance[q] * weight;
+                    total_weight += weight;
+                }
+            }
+            output.pixels[p] =
+                remodulate(sum / total_weight, albedo.pixels[p]) + emission.pixels[p];
+        }
+    }
+    output
+}
+
+fn demodulate(c: Color, albedo: Color) -> Color {
+    Color::new(
+        c.x / albedo.x.max(MIN_ALBEDO),
+        c.y / albedo.y.max(MIN_ALBEDO),
+        c.z / albedo.z.max(MIN_ALBEDO),
+    )
+}
+
+fn remodulate(c: Color, albedo: Color) -> Color {
+    Color::new(
+        c.x * albedo.x.max(MIN_ALBEDO),
+        c.y * albedo.y.max(MIN_ALBEDO),
+        c.z * albedo.z.max(MIN_ALBEDO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 12;
+    const HEIGHT: usize = 8;
+
+    fn filled(f: impl Fn(usize) -> Color) -> Image {
+        let mut image = Image::new(WIDTH, HEIGHT);
+        for x in 0..WIDTH {
+            for y in 0..HEIGHT {
+                image.set(x, y, f(x));
+            }
+        }
+        image
+    }
+
+    fn grey(v: f64) -> Color {
+        Color::new(v, v, v)
+    }
+
+    #[test]
+    fn constant_images_are_unchanged() {
+        let color = filled(|_| Color::new(0.4, 0.3, 0.2));
+        let albedo = filled(|_| Color::new(0.8, 0.6, 0.5));
+        let normal = filled(|_| Color::new(0.0, 0.0, 1.0));
+        let emission = filled(|_| Color::new(0.1, 0.0, 0.0));
+        let variance = vec![0.01; WIDTH * HEIGHT];
+        let settings = DenoiseSettings::default();
+        let output = denoise(&color, &variance, &albedo, &normal, &emission, &settings);
+        for (out, expected) in output.pixels.iter().zip(&color.pixels) {
+            assert!((*out - *expected).length() < 1e-12, "{:?}", out);
+        }
+    }
+
+    /// The denoised values of the pixels left and right of the edge between
+    /// columns 5 and 6, where the color steps from 0.2 to 0.8. The variance
+    /// is so high that the color alone would not keep them apart.
+    fn across_edge(albedo: &Image, normal: &Image) -> (f64, f64) {
+        let color = filled(|x| {
+            let albedo = albedo.get(x, 0);
+            grey(if x < WIDTH / 2 { 0.2 } else { 0.8 }) * albedo
+        });
+        let emission = filled(|_| Color::zero());
+        let variance = vec![1.0; WIDTH * HEIGHT];
+        let settings = DenoiseSettings::default();
+        let output = denoise(&color, &variance, albedo, normal, &emission, &settings);
+        let y = HEIGHT / 2;
+        let left = output.get(WIDTH / 2 - 1, y).x / albedo.get(WIDTH / 2 - 1, y).x;
+        let right = output.get(WIDTH / 2, y).x / albedo.get(WIDTH / 2, y).x;
+        (left, right)
+    }
+
+    #[test]
+    fn edges_in_the_guides_are_kept() {
+        let flat = filled(|_| Color::new(0.0, 0.0, 1.0));
+        let white = filled(|_| grey(1.0));
+
+        // Without an edge in the guides the step is smoothed out.
+        let (left, right) = across_edge(&white, &flat);
+        assert!(left > 0.3 && right < 0.7, "{} {}", left, right);
+
+        let folded = filled(|x| {
+            if x < WIDTH / 2 {
+                Color::new(1.0, 0.0, 0.0)
+            } else {
+                Color::new(0.0, 0.0, 1.0)
+            }
+        });
+        let (left, right) = across_edge(&white, &folded);
+        assert!((left - 0.2).abs() < 1e-6 && (right - 0.8).abs() < 1e-6);
+
+        let two_tone = filled(|x| grey(if x < WIDTH / 2 { 0.3 } else { 0.9 }));
+        let (left, right) = across_edge(&two_tone, &flat);
+        assert!((left - 0.2).abs() < 1e-6 && (right - 0.8).abs() < 1e-6);
+    }
+}
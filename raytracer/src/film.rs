@@ -0,0 +1,285 @@
+//! Reconstruction of pixels from samples.
+//!
+//! Every sample is splatted onto all pixels within the filter radius of it,
+//! weighted by the filter, and a pixel is the weighted sum divided by the sum
+//! of the weights. The box filter with radius 0.5 keeps every sample in its
+//! own pixel, which is a plain average.
+
+use crate::render::Tile;
+use crate::vec3::Color;
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3.
+    Mitchell,
+    /// Windowed sinc with as many lobes as the radius.
+    Lanczos,
+}
+
+impl FilterKind {
+    pub const NAMES: [&'static str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "box" => Some(Self::Box),
+            "tent" => Some(Self::Tent),
+            "gaussian" => Some(Self::Gaussian),
+            "mitchell" => Some(Self::Mitchell),
+            "lanczos" => Some(Self::Lanczos),
+            _ => None,
+        }
+    }
+
+    /// The radius the filter is usually used with, in pixels.
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell | Self::Lanczos => 2.0,
+        }
+    }
+}
+
+/// A separable pixel filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// Distance in pixels beyond which the filter is zero.
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// Number of neighbouring pixels in each direction a sample can reach.
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).ceil().max(0.0) as usize
+    }
+
+    /// Weight of a sample at offset (`dx`, `dy`) from a pixel center.
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => (1.0 - x.abs() / r).max(0.0),
+            FilterKind::Gaussian => {
+                // Three standard deviations fit in the radius, and the curve
+                // is shifted down to reach zero there.
+                let gaussian = |x: f64| (-4.5 * x * x / (r * r)).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::Box)
+    }
+}
+
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    let x = x.abs();
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + 6.0
+            - 2.0 * B
+    } else if x < 2.0 {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + 8.0 * B
+            + 24.0 * C
+    } else {
+        0.0
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Filter-weighted sums of the samples that fall on the pixels of `area`.
+pub struct Film {
+    pub area: Tile,
+    pub filter: Filter,
+    /// Weighted sum of samples per pixel of `area`, row by row.
+    pub sum: Vec<Color>,
+    /// Sum of weights per pixel of `area`.
+    pub weights: Vec<f64>,
+}
+
+impl Film {
+    pub fn new(area: Tile, filter: Filter) -> Self {
+        let pixels = (area.x1 - area.x0) * (area.y1 - area.y0);
+        Self {
+            area,
+            filter,
+            sum: vec![Color::zero(); pixels],
+            weights: vec![0.0; pixels],
+        }
+    }
+
+    /// Index of pixel (`x`, `y`) in `sum` and `weights`.
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.area.y0) * (self.area.x1 - self.area.x0) + x - self.area.x0
+    }
+
+    /// Splats `color`, sampled at `offset` from the center of pixel (`x`,
+    /// `y`), onto the pixels of `area` it reaches. Offsets lie in
+    /// `[-0.5, 0.5)` horizontally and `(-0.5, 0.5]` vertically, so samples on
+    /// a pixel border count for one side only.
+    pub fn add_sample(&mut self, x: usize, y: usize, offset: (f64, f64), color: Color) {
+        let r = self.filter.radius;
+        let margin = self.filter.margin() as isize;
+        for ky in -margin..=margin {
+            let dy = offset.1 - ky as f64;
+            let py = y as isize + ky;
+            if dy <= -r || dy > r || py < self.area.y0 as isize || py >= self.area.y1 as isize {
+                continue;
+            }
+            for kx in -margin..=margin {
+                let dx = offset.0 - kx as f64;
+                let px = x as isize + kx;
+                if dx < -r || dx >= r || px < self.area.x0 as isize || px >= self.area.x1 as isize {
+                    continue;
+                }
+                let weight = self.filter.evaluate(dx, dy);
+                let index = self.index(px as usize, py as usize);
+                self.sum[index] += color * weight;
+                self.weights[index] += weight;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> impl Iterator<Item = Filter> {
+        FilterKind::NAMES
+            .iter()
+            .map(|name| Filter::new(FilterKind::from_name(name).unwrap()))
+    }
+
+    fn film(filter: Filter) -> Film {
+        let area = Tile {
+            x0: 0,
+            y0: 0,
+            x1: 5,
+            y1: 5,
+        };
+        Film::new(area, filter)
+    }
+
+    #[test]
+    fn filters_vanish_at_their_radius() {
+        for filter in filters() {
+            let r = filter.radius;
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{:?}", filter);
+            if filter.kind != FilterKind::Box {
+                assert!(filter.evaluate(r, 0.0).abs() < 1e-12, "{:?}", filter);
+                assert!(filter.evaluate(0.0, -r).abs() < 1e-12, "{:?}", filter);
+                assert!(filter.evaluate(0.9 * r, 0.0) < filter.evaluate(0.1 * r, 0.0));
+            }
+            // Symmetric and separable.
+            let (dx, dy) = (0.3 * r, 0.7 * r);
+            assert_eq!(filter.evaluate(dx, dy), filter.evaluate(-dx, dy));
+            assert_eq!(filter.evaluate(dx, dy), filter.evaluate(dy, dx));
+        }
+        let margins: Vec<usize> = filters().map(|f| f.margin()).collect();
+        assert_eq!(margins, [0, 1, 1, 2, 2]);
+        let wide_box = Filter {
+            kind: FilterKind::Box,
+            radius: 0.6,
+        };
+        assert_eq!(wide_box.margin(), 1);
+    }
+
+    #[test]
+    fn constant_samples_give_constant_pixels() {
+        // Whatever the filter's scale, dividing by the summed weights
+        // normalizes it, so a uniform image comes out unchanged.
+        let color = Color::new(0.25, 0.5, 2.0);
+        for filter in filters() {
+            let mut film = film(filter);
+            for y in 0..5 {
+                for x in 0..5 {
+                    for (i, j) in [(0, 0), (1, 2), (3, 1), (2, 3)] {
+                        let offset = (i as f64 / 4.0 - 0.5, 0.5 - j as f64 / 4.0);
+                        film.add_sample(x, y, offset, color);
+                    }
+                }
+            }
+            for (sum, &weight) in film.sum.iter().zip(&film.weights) {
+                assert!(weight > 0.0, "{:?}", filter);
+                assert!((*sum / weight - color).length() < 1e-12, "{:?}", filter);
+            }
+        }
+    }
+
+    #[test]
+    fn splats_reach_the_pixels_within_the_radius() {
+        let weights = |filter: Filter, offset: (f64, f64)| -> Vec<f64> {
+            let mut film = film(filter);
+            film.add_sample(2, 2, offset, Color::new(1.0, 1.0, 1.0));
+            film.weights
+        };
+        let at = |x: usize, y: usize| y * 5 + x;
+
+        // The box filter keeps samples in their pixel, also on the border.
+        for offset in [(0.25, 0.0), (-0.5, 0.5), (0.49, -0.49)] {
+            let box_weights = weights(Filter::default(), offset);
+            assert_eq!(box_weights[at(2, 2)], 1.0);
+            assert_eq!(box_weights.iter().sum::<f64>(), 1.0);
+        }
+
+        // A tent of radius 1 shares a sample between the two nearest
+        // columns by distance.
+        let tent = weights(Filter::new(FilterKind::Tent), (0.25, 0.0));
+        assert_eq!(tent[at(2, 2)], 0.75);
+        assert_eq!(tent[at(3, 2)], 0.25);
+        assert_eq!(tent.iter().sum::<f64>(), 1.0);
+
+        // A box of radius 1 counts a sample fully in the two columns and two
+        // rows it covers; offsets grow down the image, like rows.
+        let wide_box = Filter {
+            kind: FilterKind::Box,
+            radius: 1.0,
+        };
+        let wide = weights(wide_box, (-0.25, 0.25));
+        let reached: Vec<usize> = (0..25).filter(|&i| wide[i] > 0.0).collect();
+        assert_eq!(reached, [at(1, 2), at(2, 2), at(1, 3), at(2, 3)]);
+        assert!(reached.iter().all(|&i| wide[i] == 1.0));
+
+        // Splats stop at the edge of the film's area. Mitchell is zero two
+        // pixels away, so only the neighbours inside the area remain.
+        let mut film = film(Filter::new(FilterKind::Mitchell));
+        film.add_sample(0, 4, (0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        let reached: Vec<usize> = (0..25).filter(|&i| film.weights[i] != 0.0).collect();
+        assert_eq!(reached, [at(0, 3), at(1, 3), at(0, 4), at(1, 4)]);
+    }
+}
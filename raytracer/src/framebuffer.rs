@@ -0,0 +1,42 @@
+use crate::tonemap::ToneMapping;
+use crate::vec3::Color;
+use image::{Rgb, Rgb32FImage, RgbImage};
+
+/// A linear, floating-point picture, top row first.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::zero(); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Tone-mapped 8-bit copy.
+    pub fn to_rgb8(&self, tone_mapping: &ToneMapping) -> RgbImage {
+        tone_mapping.apply(self)
+    }
+
+    /// Linear 32-bit float copy for high dynamic range formats.
+    pub fn to_rgb32f(&self) -> Rgb32FImage {
+        Rgb32FImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let c = self.get(x as usize, y as usize);
+            Rgb([c.x as f32, c.y as f32, c.z as f32])
+        })
+    }
+}
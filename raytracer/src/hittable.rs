@@ -1,4 +1,6 @@
 use crate::aabb::Aabb;
+use crate::bvh::BvhStats;
+use crate::mat4::Mat4;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::rtweekend::degrees_to_radians;
@@ -9,6 +11,7 @@ pub use std::sync::Arc;
 
 const INFINITY: f64 = f64::INFINITY;
 
+/// Where and how a ray hit a surface.
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
@@ -46,11 +49,47 @@ impl HitRecord {
     }
 }
 
+/// Anything a ray can intersect.
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Solid-angle density with which [`Hittable::random`] picks `direction`
+    /// from `origin`. Objects that cannot be sampled return zero and must not
+    /// be used as lights.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// A direction from `origin` towards a point on the object, chosen with
+    /// the 2D sample `u`.
+    fn random(&self, _origin: &Point3, _u: (f64, f64)) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    /// Shape of the bounding volume hierarchies inside the object, or `None`
+    /// for an object that is intersected as a whole.
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        None
+    }
+
+    /// Calls `visit` with every material a hit on the object can report, in
+    /// an order that only depends on how the object was built.
+    fn materials(&self, _visit: &mut dyn FnMut(&Arc<dyn Material>)) {}
+}
+
+/// Converts the area density `1 / area` of a sampled surface point into a
+/// solid-angle density as seen along `direction`, which hit it at `rec`.
+pub fn area_to_solid_angle(rec: &HitRecord, direction: &Vec3, area: f64) -> f64 {
+    let distance_squared = rec.t * rec.t * direction.length_squared();
+    let cosine = (direction.dot(rec.normal) / direction.length()).abs();
+    if cosine <= 0.0 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
 }
 
+/// Moves an object by a fixed offset.
 pub struct Translate {
     ptr: Arc<dyn Hittable>,
     offset: Vec3,
@@ -78,6 +117,18 @@ impl Hittable for Translate {
         }
     }
 
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.ptr.pdf_value(&(*origin - self.offset), direction)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        self.ptr.random(&(*origin - self.offset), u)
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        self.ptr.bvh_stats()
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         if let Some(mut output_box) = self.ptr.bounding_box(time0, time1) {
             output_box = Aabb::new(
@@ -89,8 +140,13 @@ impl Hittable for Translate {
             None
         }
     }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.ptr.materials(visit);
+    }
 }
 
+/// Rotates an object about the y axis by an angle in degrees.
 pub struct RotateY {
     ptr: Arc<dyn Hittable>,
     sin_theta: f64,
@@ -105,14 +161,14 @@ impl RotateY {
         let cos_theta = radians.cos();
         let mut bbox = p.bounding_box(0.0, 1.0);
         if let Some(b) = bbox {
-            let mut max0 = Point3::new(INFINITY, INFINITY, INFINITY);
-            let mut min0 = Point3::new(-INFINITY, -INFINITY, -INFINITY);
+            let mut min0 = Point3::new(INFINITY, INFINITY, INFINITY);
+            let mut max0 = Point3::new(-INFINITY, -INFINITY, -INFINITY);
             for i in 0..2 {
                 for j in 0..2 {
                     for k in 0..2 {
                         let x = i as f64 * b.maximum.x + (1 - i) as f64 * b.minimum.x;
-                        let y = j as f64 * b.maximum.y + (1 - j) as f64 * b.minimum.x;
-                        let z = k as f64 * b.maximum.z + (1 - k) as f64 * b.minimum.x;
+                        let y = j as f64 * b.maximum.y + (1 - j) as f64 * b.minimum.y;
+                        let z = k as f64 * b.maximum.z + (1 - k) as f64 * b.minimum.z;
 
                         let newx = cos_theta * x + sin_theta * z;
                         let newz = -sin_theta * x + cos_theta * z;
@@ -135,6 +191,24 @@ impl RotateY {
             bbox,
         }
     }
+
+    /// Rotates a world-space vector into the object's frame.
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    /// Rotates an object-space vector back into the world frame.
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
 }
 
 impl Hittable for RotateY {
@@ -172,4 +246,175 @@ impl Hittable for RotateY {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.ptr
+            .pdf_value(&self.to_object(*origin), &self.to_object(*direction))
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        self.to_world(self.ptr.random(&self.to_object(*origin), u))
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        self.ptr.bvh_stats()
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.ptr.materials(visit);
+    }
+}
+
+/// Places an object with an affine transform, which may rotate, scale
+/// unevenly, shear and translate it.
+pub struct Transform {
+    ptr: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    /// Transpose of `inverse`, which takes normals to world space.
+    normal_matrix: Mat4,
+    /// Light sampling is only delegated through transforms that keep solid
+    /// angles.
+    similarity: bool,
+}
+
+impl Transform {
+    /// Transforms `p` by `matrix`; `None` if the matrix cannot be inverted.
+    pub fn new(p: Arc<dyn Hittable>, matrix: Mat4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        Some(Self {
+            ptr: p,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            similarity: matrix.is_similarity(),
+        })
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // The direction is not normalized, so `t` is the same in both frames.
+        let object_r = Ray::new(
+            &self.inverse.transform_point(&ray.orig),
+            &self.inverse.transform_vector(&ray.dir),
+            ray.tm,
+        );
+        let mut rec = self.ptr.hit(&object_r, t_min, t_max)?;
+        let outward_normal = if rec.front_face {
+            rec.normal
+        } else {
+            Vec3::zero().sub(rec.normal)
+        };
+        rec.p = self.matrix.transform_point(&rec.p);
+        let normal = self.normal_matrix.transform_vector(&outward_normal);
+        rec.set_face_normal(ray, &normal.unit_vector());
+        Some(rec)
+    }
+
+    /// The transformed corners of the object's box, which bound the
+    /// transformed object as tightly as the box bounds the object.
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let b = self.ptr.bounding_box(time0, time1)?;
+        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { b.minimum.x } else { b.maximum.x },
+                if i & 2 == 0 { b.minimum.y } else { b.maximum.y },
+                if i & 4 == 0 { b.minimum.z } else { b.maximum.z },
+            );
+            let p = self.matrix.transform_point(&corner);
+            for c in 0..3 {
+                min[c] = min[c].min(p[c]);
+                max[c] = max[c].max(p[c]);
+            }
+        }
+        Some(Aabb::new(&min, &max))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if !self.similarity {
+            return 0.0;
+        }
+        self.ptr.pdf_value(
+            &self.inverse.transform_point(origin),
+            &self.inverse.transform_vector(direction),
+        )
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        let direction = self.ptr.random(&self.inverse.transform_point(origin), u);
+        self.matrix.transform_vector(&direction)
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        self.ptr.bvh_stats()
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.ptr.materials(visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::box_object::BoxObject;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn rotate_y_bounds_rotated_box() {
+        let unit_box = BoxObject::new(Point3::zero(), Point3::ones(), material());
+        let rotated = RotateY::new(Arc::new(unit_box), 45.0);
+        let b = rotated.bounding_box(0.0, 1.0).unwrap();
+        let h = 0.5f64.sqrt();
+        assert_close(b.minimum, Point3::new(0.0, 0.0, -h));
+        assert_close(b.maximum, Point3::new(2.0 * h, 1.0, h));
+    }
+
+    #[test]
+    fn transform_bounds_transformed_corners() {
+        let sphere = Arc::new(Sphere::new(Point3::zero(), 1.0, material()));
+        let matrix =
+            Mat4::translate(Vec3::new(1.0, 2.0, 3.0)) * Mat4::scale(Vec3::new(2.0, 1.0, 0.5));
+        let b = Transform::new(sphere, matrix)
+            .unwrap()
+            .bounding_box(0.0, 1.0)
+            .unwrap();
+        assert_close(b.minimum, Point3::new(-1.0, 1.0, 2.5));
+        assert_close(b.maximum, Point3::new(3.0, 3.0, 3.5));
+    }
+
+    #[test]
+    fn transform_hits_scaled_sphere() {
+        let sphere = Arc::new(Sphere::new(Point3::zero(), 1.0, material()));
+        let matrix =
+            Mat4::translate(Vec3::new(0.0, 0.0, -5.0)) * Mat4::scale(Vec3::new(1.0, 1.0, 2.0));
+        let ellipsoid = Transform::new(sphere, matrix).unwrap();
+        let ray = Ray::new(&Point3::zero(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = ellipsoid.hit(&ray, 0.001, INFINITY).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_close(rec.p, Point3::new(0.0, 0.0, -3.0));
+        assert_close(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+        let miss = Ray::new(&Point3::new(1.5, 0.0, 0.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(ellipsoid.hit(&miss, 0.001, INFINITY).is_none());
+    }
+
+    #[test]
+    fn transform_rejects_singular_matrix() {
+        let sphere = Arc::new(Sphere::new(Point3::zero(), 1.0, material()));
+        assert!(Transform::new(sphere, Mat4::scale(Vec3::new(1.0, 0.0, 1.0))).is_none());
+    }
 }
@@ -1,9 +1,12 @@
 use crate::aabb::{surrounding_box, Aabb};
+use crate::bvh::BvhStats;
 use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::Point3;
-pub use std::{sync::Arc, vec};
+use crate::vec3::{Point3, Vec3};
+pub use std::sync::Arc;
 
+/// A plain list of objects, hit by testing every one of them.
 #[derive(Clone)]
 pub struct HittableList {
     pub objects: Vec<Arc<dyn Hittable>>,
@@ -65,4 +68,128 @@ impl Hittable for HittableList {
         }
         Some(output_box)
     }
+
+    /// The average density of the objects, matching [`HittableList::random`].
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    /// Samples one of the objects, each with the same probability.
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        let n = self.objects.len();
+        if n == 0 {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let scaled = u.0 * n as f64;
+        let index = (scaled as usize).min(n - 1);
+        // Reuse the rest of the first dimension for the chosen object.
+        let u0 = (scaled - index as f64).min(1.0 - f64::EPSILON);
+        self.objects[index].random(origin, (u0, u.1))
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        Some(BvhStats::of_list(&self.objects))
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        for object in &self.objects {
+            object.materials(visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XyRect;
+    use crate::material::Lambertian;
+    use crate::sampler::{sample_unit_sphere, uniform_sphere_pdf};
+    use crate::sphere::Sphere;
+    use crate::Color;
+
+    /// A weight that differs across the objects, so that a density that is
+    /// right on average but wrongly distributed still shows.
+    fn weight(direction: Vec3) -> f64 {
+        1.0 + 0.5 * direction.unit_vector().x
+    }
+
+    /// Checks that the density of `object.random` is `object.pdf_value`:
+    /// both the integral of `pdf_value` over all directions and the estimate
+    /// of the integral of [`weight`] over the directions that hit `object`
+    /// from its samples must agree with integrating over uniform directions.
+    fn check_pdf(object: &dyn Hittable, origin: Point3) {
+        let (nz, nphi) = (2048, 512);
+        let (mut pdf_integral, mut weight_integral) = (0.0, 0.0);
+        for i in 0..nz {
+            for j in 0..nphi {
+                let u = ((i as f64 + 0.5) / nz as f64, (j as f64 + 0.5) / nphi as f64);
+                let direction = sample_unit_sphere(u);
+                pdf_integral += object.pdf_value(&origin, &direction);
+                if object
+                    .hit(&Ray::new(&origin, &direction, 0.0), 0.001, f64::INFINITY)
+                    .is_some()
+                {
+                    weight_integral += weight(direction);
+                }
+            }
+        }
+        let scale = 1.0 / ((nz * nphi) as f64 * uniform_sphere_pdf());
+        let (pdf_integral, weight_integral) = (pdf_integral * scale, weight_integral * scale);
+        assert!((pdf_integral - 1.0).abs() < 0.01, "{}", pdf_integral);
+
+        let n = 256;
+        let mut estimate = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let direction = object.random(&origin, u);
+                let pdf = object.pdf_value(&origin, &direction);
+                assert!(pdf > 0.0, "{:?} does not hit the object", direction);
+                estimate += weight(direction) / pdf;
+            }
+        }
+        estimate /= (n * n) as f64;
+        assert!(
+            (estimate - weight_integral).abs() < 0.01 * weight_integral,
+            "{} vs {}",
+            estimate,
+            weight_integral
+        );
+    }
+
+    fn sphere() -> Arc<dyn Hittable> {
+        let material = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        Arc::new(Sphere::new(Point3::new(0.5, 0.0, -3.0), 1.0, material))
+    }
+
+    fn quad() -> Arc<dyn Hittable> {
+        let material = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        Arc::new(XyRect::new(1.0, 3.0, -1.0, 0.5, 2.0, material))
+    }
+
+    #[test]
+    fn sphere_pdf_matches_its_samples() {
+        check_pdf(sphere().as_ref(), Point3::new(0.0, 0.2, 0.0));
+    }
+
+    #[test]
+    fn quad_pdf_matches_its_samples() {
+        check_pdf(quad().as_ref(), Point3::new(0.0, 0.2, 0.0));
+    }
+
+    #[test]
+    fn list_pdf_matches_its_samples() {
+        let mut list = HittableList::new();
+        list.add(sphere());
+        list.add(quad());
+        check_pdf(&list, Point3::new(0.0, 0.2, 0.0));
+    }
 }
@@ -0,0 +1,184 @@
+//! Instancing: one piece of geometry placed many times.
+//!
+//! The geometry, usually a [`BvhNode`](crate::bvh::BvhNode), is the bottom
+//! level of a two-level acceleration structure and is stored once; every
+//! [`Instance`] only holds a pointer to it, a transform and optionally a
+//! material. Adding the instances to the world puts them under the top-level
+//! BVH built at render time.
+
+use crate::aabb::Aabb;
+use crate::bvh::BvhStats;
+use crate::hittable::{HitRecord, Hittable, Transform};
+use crate::mat4::Mat4;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Shared geometry under an affine transform.
+pub struct Instance {
+    transform: Transform,
+    /// Replaces the materials of the geometry when set.
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    /// Places `geometry` with `matrix`; `None` if the matrix cannot be
+    /// inverted.
+    pub fn new(
+        geometry: Arc<dyn Hittable>,
+        matrix: Mat4,
+        material: Option<Arc<dyn Material>>,
+    ) -> Option<Self> {
+        Some(Self {
+            transform: Transform::new(geometry, matrix)?,
+            material,
+        })
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.transform.hit(ray, t_min, t_max)?;
+        if let Some(material) = &self.material {
+            rec.mat_ptr = material.clone();
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.transform.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.transform.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        self.transform.random(origin, u)
+    }
+
+    /// The geometry's hierarchy, counted once per instance.
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        self.transform.bvh_stats()
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        match &self.material {
+            Some(material) => visit(material),
+            None => self.transform.materials(visit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::{BvhNode, BvhSettings};
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+
+    fn material(grey: f64) -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_color(&Color::new(grey, grey, grey)))
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn override_material_replaces_the_geometry_material() {
+        let own = material(0.2);
+        let replacement = material(0.8);
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Point3::zero(), 1.0, own.clone()));
+        let plain = Instance::new(sphere.clone(), Mat4::identity(), None).unwrap();
+        let painted = Instance::new(sphere, Mat4::identity(), Some(replacement.clone())).unwrap();
+        let ray = Ray::new(&Point3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let hit = |instance: &Instance| instance.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(Arc::ptr_eq(&hit(&plain).mat_ptr, &own));
+        assert!(Arc::ptr_eq(&hit(&painted).mat_ptr, &replacement));
+
+        let mut visited = Vec::new();
+        painted.materials(&mut |m| visited.push(m.clone()));
+        assert_eq!(visited.len(), 1);
+        assert!(Arc::ptr_eq(&visited[0], &replacement));
+    }
+
+    #[test]
+    fn instances_share_geometry_but_not_placement() {
+        let spheres: Vec<Arc<dyn Hittable>> = (0..4)
+            .map(|i| {
+                let center = Point3::new(3.0 * i as f64, 0.0, 0.0);
+                Arc::new(Sphere::new(center, 1.0, material(0.5))) as Arc<dyn Hittable>
+            })
+            .collect();
+        let (geometry, _) = BvhNode::build(spheres, 0.0, 1.0, &BvhSettings::default());
+        let left = Instance::new(
+            geometry.clone(),
+            Mat4::translate(Vec3::new(0.0, 10.0, 0.0)),
+            None,
+        )
+        .unwrap();
+        let right = Instance::new(
+            geometry,
+            Mat4::translate(Vec3::new(0.0, -10.0, 0.0)) * Mat4::scale(Vec3::new(2.0, 2.0, 2.0)),
+            None,
+        )
+        .unwrap();
+
+        // Down the z axis through the third sphere of each copy.
+        let left_ray = Ray::new(
+            &Point3::new(6.0, 10.0, -10.0),
+            &Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        let rec = left.hit(&left_ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 9.0).abs() < 1e-9);
+        assert_close(rec.p, Point3::new(6.0, 10.0, -1.0));
+        assert_close(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        let right_ray = Ray::new(
+            &Point3::new(12.0, -10.0, -10.0),
+            &Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        let rec = right.hit(&right_ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 8.0).abs() < 1e-9);
+        assert_close(rec.p, Point3::new(12.0, -10.0, -2.0));
+        assert_close(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        // Each copy only sees its own rays.
+        assert!(left.hit(&right_ray, 0.001, f64::INFINITY).is_none());
+        assert!(right.hit(&left_ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn light_sampling_follows_the_transform() {
+        // A unit sphere scaled by 2 and moved stands in for a sphere of
+        // radius 2 in its place.
+        let unit: Arc<dyn Hittable> = Arc::new(Sphere::new(Point3::zero(), 1.0, material(0.5)));
+        let matrix = Mat4::translate(Vec3::new(1.0, 4.0, -2.0)) * Mat4::scale(Vec3::ones() * 2.0);
+        let instance = Instance::new(unit.clone(), matrix, None).unwrap();
+        let sphere = Sphere::new(Point3::new(1.0, 4.0, -2.0), 2.0, material(0.5));
+        let origin = Point3::new(-3.0, 0.5, 6.0);
+        for u in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            let direction = instance.random(&origin, u);
+            assert_close(
+                direction.unit_vector(),
+                sphere.random(&origin, u).unit_vector(),
+            );
+            let pdf = instance.pdf_value(&origin, &direction);
+            assert!(pdf > 0.0);
+            assert!((pdf - sphere.pdf_value(&origin, &direction)).abs() < 1e-9 * pdf);
+        }
+        let away = Vec3::new(0.0, 0.0, 1.0);
+        assert_eq!(instance.pdf_value(&origin, &away), 0.0);
+
+        // Stretching changes solid angles in a way the geometry's density
+        // does not know, so the instance is not sampled.
+        let stretched = Instance::new(unit, Mat4::scale(Vec3::new(1.0, 3.0, 1.0)), None).unwrap();
+        let direction = stretched.random(&origin, (0.5, 0.5));
+        assert_eq!(stretched.pdf_value(&origin, &direction), 0.0);
+    }
+}
@@ -0,0 +1,506 @@
+//! Integrators turn a camera ray into the value of one pixel sample.
+//!
+//! Besides the path tracers there are cheaper approximations and debug views
+//! that show a property of the first hit instead of radiance. Depth and BVH
+//! cost are written unscaled, so view them in an EXR or HDR file or lower the
+//! exposure.
+
+use crate::aov::{material_color, MaterialIds};
+use crate::bvh::take_nodes_visited;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::ScatterRecord;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::render::RenderSettings;
+use crate::sampler::{sample_cosine_hemisphere, Sampler};
+use crate::scene::Scene;
+use crate::vec3::Color;
+use std::ops::Mul;
+use std::sync::Arc;
+
+const INFINITY: f64 = f64::INFINITY;
+/// Bounces before Russian roulette may end a path.
+const ROULETTE_DEPTH: u8 = 3;
+/// Upper bound of the survival probability, so that paths which keep all
+/// their energy, e.g. between mirrors, still end eventually.
+const MAX_SURVIVAL: f64 = 0.95;
+
+pub trait Integrator: Send + Sync {
+    /// The value of one sample along the camera ray `r`, drawing random
+    /// numbers from the next dimensions of `sampler`, and the first hit along
+    /// `r`, if any, for the AOVs.
+    fn ray_color(&self, r: &Ray, sampler: &mut dyn Sampler) -> (Color, Option<HitRecord>);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegratorKind {
+    /// Path tracing with light sampling, see [`LightPathTracer`].
+    Path,
+    /// Path tracing that only finds lights by following scattered rays.
+    Naive,
+    /// Light arriving directly from emitters, after specular bounces only.
+    Direct,
+    /// Fraction of the hemisphere that is unoccluded, see
+    /// [`AmbientOcclusion`].
+    AmbientOcclusion,
+    Normals,
+    Uv,
+    Depth,
+    MaterialId,
+    BvhCost,
+}
+
+impl IntegratorKind {
+    pub const NAMES: [&'static str; 9] = [
+        "path",
+        "naive",
+        "direct",
+        "ao",
+        "normals",
+        "uv",
+        "depth",
+        "material_id",
+        "bvh_cost",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "path" => Some(Self::Path),
+            "naive" => Some(Self::Naive),
+            "direct" => Some(Self::Direct),
+            "ao" => Some(Self::AmbientOcclusion),
+            "normals" => Some(Self::Normals),
+            "uv" => Some(Self::Uv),
+            "depth" => Some(Self::Depth),
+            "material_id" => Some(Self::MaterialId),
+            "bvh_cost" => Some(Self::BvhCost),
+            _ => None,
+        }
+    }
+
+    /// An integrator of this kind for `scene`, tracing rays against `world`,
+    /// which should hold the objects of `scene.world` in an acceleration
+    /// structure.
+    pub fn build(
+        &self,
+        scene: &Scene,
+        world: Arc<dyn Hittable>,
+        settings: &RenderSettings,
+    ) -> Arc<dyn Integrator> {
+        let background = scene.background;
+        let max_depth = settings.max_depth;
+        let lights = Arc::new(scene.lights.clone());
+        match self {
+            Self::Path => Arc::new(LightPathTracer::new(
+                world, lights, background, max_depth, false,
+            )),
+            Self::Naive => Arc::new(PathTracer::new(world, background, max_depth)),
+            Self::Direct => Arc::new(LightPathTracer::new(
+                world, lights, background, max_depth, true,
+            )),
+            Self::AmbientOcclusion => Arc::new(AmbientOcclusion::new(world, settings.ao_radius)),
+            Self::Normals => Arc::new(DebugIntegrator::new(world, DebugView::Normals)),
+            Self::Uv => Arc::new(DebugIntegrator::new(world, DebugView::Uv)),
+            Self::Depth => Arc::new(DebugIntegrator::new(world, DebugView::Depth)),
+            Self::MaterialId => Arc::new(DebugIntegrator::new(world, DebugView::MaterialId)),
+            Self::BvhCost => Arc::new(DebugIntegrator::new(world, DebugView::BvhCost)),
+        }
+    }
+}
+
+/// Path tracing that follows the scattered rays of the materials. Paths end
+/// at a miss, at an absorbing surface, by Russian roulette, or after
+/// `max_depth` segments.
+pub struct PathTracer {
+    world: Arc<dyn Hittable>,
+    background: Color,
+    max_depth: u8,
+    roulette_depth: u8,
+}
+
+impl PathTracer {
+    pub fn new(world: Arc<dyn Hittable>, background: Color, max_depth: u8) -> Self {
+        Self {
+            world,
+            background,
+            max_depth,
+            roulette_depth: ROULETTE_DEPTH,
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn ray_color(&self, r: &Ray, sampler: &mut dyn Sampler) -> (Color, Option<HitRecord>) {
+        let mut radiance = Color::zero();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = r.clone();
+        let mut first_hit = None;
+        for bounce in 0..self.max_depth {
+            let rec = match self.world.hit(&ray, 0.001, INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    radiance += throughput.mul(self.background);
+                    break;
+                }
+            };
+            if bounce == 0 {
+                first_hit = Some(rec.clone());
+            }
+            radiance += throughput.mul(rec.mat_ptr.emitted(rec.u, rec.v, &rec.p));
+            let srec = match rec.mat_ptr.scatter(&ray, &rec, sampler) {
+                Some(srec) => srec,
+                None => break,
+            };
+            throughput = throughput.mul(srec.attenuation);
+            if bounce >= self.roulette_depth && !roulette(&mut throughput, sampler) {
+                break;
+            }
+            ray = srec.scattered;
+        }
+        (radiance, first_hit)
+    }
+}
+
+/// Path tracing with next-event estimation: every diffuse hit also samples a
+/// direction towards `lights`, and light and BSDF sampling are combined with
+/// multiple importance sampling. With `direct_only` a path ends after the
+/// first diffuse hit, leaving only direct lighting.
+pub struct LightPathTracer {
+    world: Arc<dyn Hittable>,
+    lights: Arc<dyn Hittable>,
+    background: Color,
+    max_depth: u8,
+    direct_only: bool,
+    roulette_depth: u8,
+}
+
+impl LightPathTracer {
+    pub fn new(
+        world: Arc<dyn Hittable>,
+        lights: Arc<dyn Hittable>,
+        background: Color,
+        max_depth: u8,
+        direct_only: bool,
+    ) -> Self {
+        Self {
+            world,
+            lights,
+            background,
+            max_depth,
+            direct_only,
+            roulette_depth: ROULETTE_DEPTH,
+        }
+    }
+
+    /// Radiance reflected at `rec` from a direction sampled towards the
+    /// lights, weighted against BSDF sampling. The radiance comes from
+    /// whatever the shadow ray hits first, so occluders block it.
+    fn sample_light(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let direction = self.lights.random(&rec.p, sampler.get_2d());
+        let to_light = Ray::new(&rec.p, &direction, r.tm);
+        let light_pdf = self.lights.pdf_value(&rec.p, &to_light.dir);
+        let scattering_pdf = rec.mat_ptr.scattering_pdf(r, rec, &to_light);
+        if light_pdf <= 0.0 || scattering_pdf <= 0.0 {
+            return Color::zero();
+        }
+        match self.world.hit(&to_light, 0.001, INFINITY) {
+            Some(light_rec) => {
+                let radiance = light_rec
+                    .mat_ptr
+                    .emitted(light_rec.u, light_rec.v, &light_rec.p);
+                let weight = power_heuristic(light_pdf, scattering_pdf);
+                srec.attenuation
+                    .mul(radiance)
+                    .mul(scattering_pdf / light_pdf * weight)
+            }
+            None => Color::zero(),
+        }
+    }
+}
+
+impl Integrator for LightPathTracer {
+    fn ray_color(&self, r: &Ray, sampler: &mut dyn Sampler) -> (Color, Option<HitRecord>) {
+        let mut radiance = Color::zero();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = r.clone();
+        let mut first_hit = None;
+        // Density with which `ray` was sampled at the previous hit, or `None`
+        // for camera rays and specular bounces, whose emission cannot be
+        // reached by light sampling.
+        let mut bsdf_pdf = None;
+        let mut diffuse_hit = false;
+        for bounce in 0..self.max_depth {
+            let rec = match self.world.hit(&ray, 0.001, INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    radiance += throughput.mul(self.background);
+                    break;
+                }
+            };
+            if bounce == 0 {
+                first_hit = Some(rec.clone());
+            }
+            let mut emitted = rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
+            if let Some(pdf) = bsdf_pdf {
+                let light_pdf = self.lights.pdf_value(&ray.orig, &ray.dir);
+                emitted = emitted.mul(power_heuristic(pdf, light_pdf));
+            }
+            radiance += throughput.mul(emitted);
+            if self.direct_only && diffuse_hit {
+                break;
+            }
+            let srec = match rec.mat_ptr.scatter(&ray, &rec, sampler) {
+                Some(srec) => srec,
+                None => break,
+            };
+            match srec.pdf {
+                Some(pdf) if pdf > 0.0 => {
+                    radiance += throughput.mul(self.sample_light(&ray, &rec, &srec, sampler));
+                    let scattering_pdf = rec.mat_ptr.scattering_pdf(&ray, &rec, &srec.scattered);
+                    throughput = throughput.mul(srec.attenuation).mul(scattering_pdf / pdf);
+                    bsdf_pdf = Some(pdf);
+                    diffuse_hit = true;
+                }
+                _ => {
+                    throughput = throughput.mul(srec.attenuation);
+                    bsdf_pdf = None;
+                }
+            }
+            if bounce >= self.roulette_depth && !roulette(&mut throughput, sampler) {
+                break;
+            }
+            ray = srec.scattered;
+        }
+        (radiance, first_hit)
+    }
+}
+
+/// White where a cosine-distributed ray from the first hit escapes within
+/// `radius`, black where it is blocked. Rays that miss the scene are white.
+pub struct AmbientOcclusion {
+    world: Arc<dyn Hittable>,
+    radius: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(world: Arc<dyn Hittable>, radius: f64) -> Self {
+        Self { world, radius }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn ray_color(&self, r: &Ray, sampler: &mut dyn Sampler) -> (Color, Option<HitRecord>) {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let rec = match self.world.hit(r, 0.001, INFINITY) {
+            Some(rec) => rec,
+            None => return (white, None),
+        };
+        let local = sample_cosine_hemisphere(sampler.get_2d());
+        let direction = Onb::build_from_w(&rec.normal).local(&local);
+        let probe = Ray::new(&rec.p, &direction, r.tm);
+        let color = match self.world.hit(&probe, 0.001, self.radius) {
+            Some(_) => Color::zero(),
+            None => white,
+        };
+        (color, Some(rec))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    /// The surface normal facing the ray, mapped from `[-1, 1]` to `[0, 1]`.
+    Normals,
+    /// Texture coordinates in the red and green channels.
+    Uv,
+    /// Distance to the first hit.
+    Depth,
+    /// A color per material.
+    MaterialId,
+    /// Number of BVH nodes visited to find the first hit.
+    BvhCost,
+}
+
+/// Shows a property of the first hit; misses are black.
+pub struct DebugIntegrator {
+    world: Arc<dyn Hittable>,
+    view: DebugView,
+    /// Materials numbered in the order `world` lists them.
+    material_ids: MaterialIds,
+}
+
+impl DebugIntegrator {
+    pub fn new(world: Arc<dyn Hittable>, view: DebugView) -> Self {
+        let material_ids = match view {
+            DebugView::MaterialId => MaterialIds::new(world.as_ref()),
+            _ => MaterialIds::default(),
+        };
+        Self {
+            world,
+            view,
+            material_ids,
+        }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn ray_color(&self, r: &Ray, _sampler: &mut dyn Sampler) -> (Color, Option<HitRecord>) {
+        take_nodes_visited();
+        let hit = self.world.hit(r, 0.001, INFINITY);
+        if self.view == DebugView::BvhCost {
+            let nodes = take_nodes_visited() as f64;
+            return (Color::new(nodes, nodes, nodes), hit);
+        }
+        let rec = match hit {
+            Some(rec) => rec,
+            None => return (Color::zero(), None),
+        };
+        let color = match self.view {
+            DebugView::Normals => (rec.normal + Color::new(1.0, 1.0, 1.0)).mul(0.5),
+            DebugView::Uv => Color::new(rec.u, rec.v, 0.0),
+            DebugView::Depth => {
+                let depth = rec.t * r.dir.length();
+                Color::new(depth, depth, depth)
+            }
+            DebugView::MaterialId => material_color(&rec, &self.material_ids),
+            DebugView::BvhCost => unreachable!(),
+        };
+        (color, Some(rec))
+    }
+}
+
+/// Russian roulette, from `ROULETTE_DEPTH` bounces on: a path survives with
+/// a probability that follows its throughput, and survivors are weighted up
+/// so the estimate stays unbiased. Returns whether the path continues.
+fn roulette(throughput: &mut Color, sampler: &mut dyn Sampler) -> bool {
+    let survival = throughput
+        .x
+        .max(throughput.y)
+        .max(throughput.z)
+        .min(MAX_SURVIVAL);
+    if sampler.get_1d() >= survival {
+        return false;
+    }
+    *throughput /= survival;
+    true
+}
+
+/// Weight of a sample drawn with density `pdf` that another strategy would
+/// have drawn with density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::rtweekend::seed_rng;
+    use crate::sampler::SamplerKind;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn ray_color_returns_first_hit() {
+        seed_rng(1);
+        let scene = Scene::by_name("cornell_box").unwrap();
+        let settings = RenderSettings::for_scene(&scene);
+        let world: Arc<dyn Hittable> = Arc::new(scene.world.clone());
+        let origin = Point3::new(278.0, 278.0, -800.0);
+        let hit = Ray::new(&origin, &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let miss = Ray::new(&origin, &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let expected = world.hit(&hit, 0.001, INFINITY).unwrap();
+        for name in IntegratorKind::NAMES {
+            let integrator =
+                IntegratorKind::from_name(name)
+                    .unwrap()
+                    .build(&scene, world.clone(), &settings);
+            let mut sampler = SamplerKind::Independent.build(1, 1);
+            let rec = integrator.ray_color(&hit, sampler.as_mut()).1;
+            assert_eq!(rec.map(|rec| rec.t), Some(expected.t), "{}", name);
+            assert!(integrator.ray_color(&miss, sampler.as_mut()).1.is_none());
+        }
+    }
+
+    /// Mean and standard error of the red channel of `n` samples along `r`.
+    fn estimate(integrator: &dyn Integrator, r: &Ray, n: u32) -> (f64, f64) {
+        seed_rng(5);
+        let mut sampler = SamplerKind::Independent.build(5, 1);
+        let (mut sum, mut sum_squared) = (0.0, 0.0);
+        for index in 0..n {
+            sampler.start_sample(0, 0, index);
+            let value = integrator.ray_color(r, sampler.as_mut()).0.x;
+            sum += value;
+            sum_squared += value * value;
+        }
+        let mean = sum / n as f64;
+        let variance = (sum_squared / n as f64 - mean * mean).max(0.0);
+        (mean, (variance / n as f64).sqrt())
+    }
+
+    #[test]
+    fn roulette_does_not_change_the_mean() {
+        // A closed room of a bright diffuse material, so that most paths
+        // bounce long enough to meet the roulette.
+        let mut world = HittableList::new();
+        let wall = Arc::new(Lambertian::new_color(&Color::new(0.8, 0.8, 0.8)));
+        let lamp = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
+        let light: Arc<dyn Hittable> = Arc::new(Sphere::new(Point3::new(0.0, 5.0, 0.0), 2.0, lamp));
+        world.add(Arc::new(Sphere::new(Point3::zero(), 10.0, wall)));
+        world.add(light.clone());
+        let world: Arc<dyn Hittable> = Arc::new(world);
+        let mut lights = HittableList::new();
+        lights.add(light);
+        let lights: Arc<dyn Hittable> = Arc::new(lights);
+
+        let r = Ray::new(&Point3::zero(), &Vec3::new(1.0, -0.5, 0.3), 0.0);
+        let black = Color::zero();
+        let max_depth = 40;
+        let tracers: [(Box<dyn Integrator>, Box<dyn Integrator>); 2] = [
+            (
+                Box::new(PathTracer::new(world.clone(), black, max_depth)),
+                Box::new(PathTracer {
+                    roulette_depth: max_depth,
+                    ..PathTracer::new(world.clone(), black, max_depth)
+                }),
+            ),
+            (
+                Box::new(LightPathTracer::new(
+                    world.clone(),
+                    lights.clone(),
+                    black,
+                    max_depth,
+                    false,
+                )),
+                Box::new(LightPathTracer {
+                    roulette_depth: max_depth,
+                    ..LightPathTracer::new(world, lights, black, max_depth, false)
+                }),
+            ),
+        ];
+        for (with_roulette, without_roulette) in tracers {
+            let (mean, error) = estimate(with_roulette.as_ref(), &r, 8000);
+            let (reference, reference_error) = estimate(without_roulette.as_ref(), &r, 8000);
+            let tolerance = 4.0 * (error * error + reference_error * reference_error).sqrt();
+            assert!(reference > 0.0);
+            assert!(
+                (mean - reference).abs() < tolerance,
+                "{} vs {} (tolerance {})",
+                mean,
+                reference,
+                tolerance
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! A CPU path tracer following the "Ray Tracing in One Weekend" series.
+//!
+//! A [`Scene`] bundles the objects of the world with a camera setup and a
+//! background color. Scenes come from the built-in builders in [`scene`], from
+//! `.scene` description files via [`scene_file::load`], or can be assembled
+//! by hand from the primitives, materials and textures exported here:
+//!
+//! ```no_run
+//! use raytracer::material::Lambertian;
+//! use raytracer::sphere::Sphere;
+//! use raytracer::{render, Color, HittableList, Point3, RenderSettings, Scene, ToneMapping};
+//! use std::sync::Arc;
+//!
+//! let mut world = HittableList::new();
+//! let red = Arc::new(Lambertian::new_color(&Color::new(0.65, 0.05, 0.05)));
+//! world.add(Arc::new(Sphere::new(Point3::zero(), 1.0, red)));
+//!
+//! let scene = Scene {
+//!     background: Color::new(0.7, 0.8, 1.0),
+//!     ..Scene::new(world)
+//! };
+//! let settings = RenderSettings::for_scene(&scene);
+//! let image = render(&scene, &settings);
+//! image.to_rgb8(&ToneMapping::default()).save("sphere.png").unwrap();
+//! ```
+
+pub mod aabb;
+pub mod aarect;
+pub mod aov;
+pub mod box_object;
+pub mod bvh;
+pub mod camera;
+pub mod color;
+pub mod constant_medium;
+pub mod denoise;
+pub mod film;
+pub mod framebuffer;
+pub mod hittable;
+pub mod hittable_list;
+pub mod instance;
+pub mod integrator;
+pub mod mat4;
+pub mod material;
+pub mod moving_sphere;
+pub mod obj_loader;
+pub mod onb;
+pub mod output;
+pub mod perlin;
+pub mod ray;
+pub mod render;
+pub mod render_state;
+pub mod rtweekend;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod sphere;
+pub mod texture;
+pub mod tonemap;
+pub mod triangle;
+pub mod vec3;
+
+pub use bvh::BvhNode;
+pub use camera::{CameraConfig, CameraModel};
+pub use framebuffer::Image;
+pub use hittable::{HitRecord, Hittable};
+pub use hittable_list::HittableList;
+pub use material::Material;
+pub use output::{write_image, OutputFormat, OutputSettings};
+pub use render::{render, RenderSettings};
+pub use render_state::RenderState;
+pub use scene::Scene;
+pub use texture::Texture;
+pub use tonemap::{ToneMap, ToneMapping};
+pub use vec3::{Color, Point3, Vec3};
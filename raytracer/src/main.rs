@@ -1,432 +1,55 @@
-mod aabb;
-mod aarect;
-mod box_object;
-mod bvh;
-mod camera;
-mod color;
-mod constant_medium;
-mod hittable;
-mod hittable_list;
-mod material;
-mod moving_sphere;
-mod perlin;
-mod ray;
-mod rtweekend;
-mod sphere;
-mod texture;
-mod vec3;
-
-use crate::aarect::{XyRect, XzRect, YzRect};
-use crate::box_object::BoxObject;
-use crate::bvh::BvhNode;
-use crate::camera::Camera;
-use crate::constant_medium::ConstantMedium;
-use crate::hittable::{Hittable, RotateY, Translate};
-use crate::hittable_list::HittableList;
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
-use crate::moving_sphere::MovingSphere;
-use crate::ray::Ray;
-use crate::rtweekend::{random_f64, random_f64_range};
-use crate::sphere::Sphere;
-use crate::texture::NoiseTexture;
-use crate::texture::{CheckerTexture, ImageTexture};
-use crate::vec3::Color;
-use crate::vec3::Point3;
-use color::write_color;
-use image::ImageBuffer;
-use indicatif::ProgressBar;
-use material::Material;
-use std::fs::File;
-use std::ops::{Mul, Sub};
-use std::sync::mpsc::channel;
-use std::sync::Arc;
+mod cli;
+
+use crate::cli::{Command, SceneSource, USAGE};
+use raytracer::denoise;
+use raytracer::render::render_progressive;
+use raytracer::rtweekend::seed_rng;
+use raytracer::scene::SCENE_NAMES;
+use raytracer::tonemap::Transfer;
+use raytracer::{
+    scene_file, write_image, OutputFormat, OutputSettings, RenderSettings, RenderState, Scene,
+    ToneMapping,
+};
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
-use threadpool::ThreadPool;
-pub use vec3::Vec3;
 
 const AUTHOR: &str = "Celery";
-const INFINITY: f64 = f64::INFINITY;
 
 fn is_ci() -> bool {
     option_env!("CI").unwrap_or_default() == "true"
 }
 
-fn ray_color(r: &Ray, background: &Color, world: &dyn Hittable, depth: u8) -> Color {
-    // If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth == 0 {
-        return Color::zero();
-    }
-    // If the ray hits nothing, return the background color.
-    if let Some(rec) = world.hit(r, 0.001, INFINITY) {
-        let emitted = rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
-        if let Some((attenuation, scattered)) = rec.mat_ptr.scatter(r, &rec) {
-            emitted + attenuation.mul(ray_color(&scattered, background, world, depth - 1))
-        } else {
-            emitted
-        }
-    } else {
-        *background
+/// `output` with `name` inserted before the extension.
+fn aov_path(output: &Path, name: &str) -> PathBuf {
+    let mut file_name = output.file_stem().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(name);
+    if let Some(extension) = output.extension() {
+        file_name.push(".");
+        file_name.push(extension);
     }
+    output.with_file_name(file_name)
 }
 
-pub fn random_scene() -> HittableList {
-    let mut world = HittableList::new();
-    let checker = Arc::new(CheckerTexture::new_color(
-        Color::new(0.2, 0.3, 0.1),
-        Color::new(0.9, 0.9, 0.9),
-    ));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Arc::new(Lambertian::new(checker)),
-    )));
-    for a in -11..10 {
-        for b in -11..10 {
-            let choose_mat = random_f64();
-            let center = Point3::new(
-                a as f64 + 0.9 * random_f64(),
-                0.2,
-                b as f64 + 0.9 * random_f64(),
-            );
-            if center.sub(Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let sphere_material: Arc<dyn Material>;
-                if choose_mat < 0.8 {
-                    let albedo = Color::random() * Color::random();
-                    sphere_material = Arc::new(Lambertian::new_color(&albedo));
-                    let center2 = center + Vec3::new(0.0, random_f64_range(0.0, 0.5), 0.0);
-                    world.add(Arc::new(MovingSphere::new(
-                        center,
-                        center2,
-                        0.0,
-                        1.0,
-                        0.2,
-                        sphere_material.clone(),
-                    )));
-                } else if choose_mat < 0.95 {
-                    let albedo = Color::random_range(0.5, 1.0);
-                    let fuzz = random_f64_range(0.0, 0.5);
-                    sphere_material = Arc::new(Metal::new(&albedo, &fuzz));
-                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material.clone())));
-                } else {
-                    sphere_material = Arc::new(Dielectric::new(1.5));
-                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material.clone())));
-                }
+fn main() {
+    let args = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Render(args)) => args,
+        Ok(Command::ListScenes) => {
+            for name in SCENE_NAMES {
+                println!("{}", name);
             }
+            return;
         }
-    }
-    let material1 = Arc::new(Dielectric::new(1.5));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 1.0, 0.0),
-        1.0,
-        material1,
-    )));
-
-    let material2 = Arc::new(Lambertian::new_color(&Color::new(0.4, 0.2, 0.1)));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        material2,
-    )));
-
-    let material3 = Arc::new(Metal::new(&Color::new(0.7, 0.6, 0.5), &0.0));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(4.0, 1.0, 0.0),
-        1.0,
-        material3,
-    )));
-    world
-}
-
-fn two_spheres() -> HittableList {
-    let mut objects = HittableList::new();
-    let checker = Arc::new(CheckerTexture::new_color(
-        Color::new(0.2, 0.3, 0.1),
-        Color::new(0.9, 0.9, 0.9),
-    ));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -10.0, 0.0),
-        10.0,
-        Arc::new(Lambertian::new(checker.clone())),
-    )));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 10.0, 0.0),
-        10.0,
-        Arc::new(Lambertian::new(checker)),
-    )));
-    objects
-}
-
-pub fn two_perlin_spheres() -> HittableList {
-    let mut objects = HittableList::new();
-    let pertext = Arc::new(NoiseTexture::new(4.0));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Arc::new(Lambertian::new(pertext.clone())),
-    )));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Arc::new(Lambertian::new(pertext)),
-    )));
-    objects
-}
-
-pub fn earth() -> HittableList {
-    let mut objects = HittableList::new();
-    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg"));
-    let earth_surface = Arc::new(Lambertian::new(earth_texture));
-    let globe = Arc::new(Sphere::new(Point3::zero(), 2.0, earth_surface));
-    objects.add(globe);
-    objects
-}
-
-pub fn simple_light() -> HittableList {
-    let mut objects = HittableList::new();
-    let pertext = Arc::new(NoiseTexture::new(4.0));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Arc::new(Lambertian::new(pertext.clone())),
-    )));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Arc::new(Lambertian::new(pertext)),
-    )));
-    let difflight = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
-    objects.add(Arc::new(XyRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight)));
-    objects
-}
-
-pub fn cornell_box() -> HittableList {
-    let mut objects = HittableList::new();
-    let red = Arc::new(Lambertian::new_color(&Color::new(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73)));
-    let green = Arc::new(Lambertian::new_color(&Color::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::new_color(Color::new(15.0, 15.0, 15.0)));
-    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
-    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Arc::new(XzRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    )));
-    objects.add(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        white.clone(),
-    )));
-    objects.add(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    )));
-    objects.add(Arc::new(XyRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    )));
-    let box1_0 = Arc::new(BoxObject::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    ));
-    let box1_1 = Arc::new(RotateY::new(box1_0, 15.0));
-    let box1_2 = Arc::new(Translate::new(box1_1, Vec3::new(265.0, 0.0, 295.0)));
-    objects.add(box1_2);
-
-    let box2_0 = Arc::new(BoxObject::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 165.0, 165.0),
-        white,
-    ));
-    let box2_1 = Arc::new(RotateY::new(box2_0, -18.0));
-    let box2_2 = Arc::new(Translate::new(box2_1, Vec3::new(130.0, 0.0, 65.0)));
-    objects.add(box2_2);
-    objects
-}
-
-pub fn cornell_smoke() -> HittableList {
-    let mut objects = HittableList::new();
-    let red = Arc::new(Lambertian::new_color(&Color::new(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73)));
-    let green = Arc::new(Lambertian::new_color(&Color::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::new_color(Color::new(7.0, 7.0, 7.0)));
-    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
-    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Arc::new(XzRect::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
-    )));
-    objects.add(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    )));
-    objects.add(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        white.clone(),
-    )));
-    objects.add(Arc::new(XyRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    )));
-
-    let box1_0 = Arc::new(BoxObject::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    ));
-    let box1_1 = Arc::new(RotateY::new(box1_0, 15.0));
-    let box1_2 = Arc::new(Translate::new(box1_1, Vec3::new(265.0, 0.0, 295.0)));
-
-    let box2_0 = Arc::new(BoxObject::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Point3::new(165.0, 165.0, 165.0),
-        white,
-    ));
-    let box2_1 = Arc::new(RotateY::new(box2_0, -18.0));
-    let box2_2 = Arc::new(Translate::new(box2_1, Vec3::new(130.0, 0.0, 65.0)));
-
-    objects.add(Arc::new(ConstantMedium::new_color(
-        box1_2,
-        0.01,
-        Color::zero(),
-    )));
-    objects.add(Arc::new(ConstantMedium::new_color(
-        box2_2,
-        0.01,
-        Color::new(1.0, 1.0, 1.0),
-    )));
-    objects
-}
-
-pub fn final_scene() -> HittableList {
-    let mut boxes1 = HittableList::new();
-    let ground = Arc::new(Lambertian::new_color(&Color::new(0.48, 0.83, 0.53)));
-
-    let boxes_per_side = 20;
-    for i in 0..boxes_per_side {
-        for j in 0..boxes_per_side {
-            let w = 100.0;
-            let x0 = -1000.0 + i as f64 * w;
-            let z0 = -1000.0 + j as f64 * w;
-            let y0 = 0.0;
-            let x1 = x0 + w;
-            let y1 = random_f64_range(1.0, 101.0);
-            let z1 = z0 + w;
-
-            boxes1.add(Arc::new(BoxObject::new(
-                Point3::new(x0, y0, z0),
-                Point3::new(x1, y1, z1),
-                ground.clone(),
-            )));
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
         }
-    }
-
-    let mut objects = HittableList::new();
-
-    objects.add(BvhNode::new_list(boxes1, 0.0, 1.0));
-
-    let light = Arc::new(DiffuseLight::new_color(Color::new(7.0, 7.0, 7.0)));
-    objects.add(Arc::new(XzRect::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
-    )));
-
-    let center1 = Point3::new(400.0, 400.0, 200.0);
-    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
-    let moving_sphere_material = Arc::new(Lambertian::new_color(&Color::new(0.7, 0.3, 0.1)));
-    objects.add(Arc::new(MovingSphere::new(
-        center1,
-        center2,
-        0.0,
-        1.0,
-        50.0,
-        moving_sphere_material,
-    )));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(260.0, 150.0, 45.0),
-        50.0,
-        Arc::new(Dielectric::new(1.5)),
-    )));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 150.0, 145.0),
-        50.0,
-        Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.9), &1.0)),
-    )));
-
-    let boundary = Arc::new(Sphere::new(
-        Point3::new(360.0, 150.0, 145.0),
-        70.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-    objects.add(boundary.clone());
-    objects.add(Arc::new(ConstantMedium::new_color(
-        boundary,
-        0.2,
-        Color::new(0.2, 0.4, 0.9),
-    )));
-    let boundary = Arc::new(Sphere::new(
-        Point3::zero(),
-        5000.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-    objects.add(Arc::new(ConstantMedium::new_color(
-        boundary,
-        0.0001,
-        Color::new(1.0, 1.0, 1.0),
-    )));
-
-    let emat = Arc::new(Lambertian::new(Arc::new(ImageTexture::new("earthmap.jpg"))));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(400.0, 200.0, 400.0),
-        100.0,
-        emat,
-    )));
-    let pertext = Arc::new(NoiseTexture::new(0.1));
-    objects.add(Arc::new(Sphere::new(
-        Point3::new(220.0, 280.0, 300.0),
-        80.0,
-        Arc::new(Lambertian::new(pertext)),
-    )));
-
-    let mut boxes2 = HittableList::new();
-    let white = Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73)));
-    let ns = 1000;
-    for _j in 0..ns {
-        boxes2.add(Arc::new(Sphere::new(
-            Point3::random_range(0.0, 165.0),
-            10.0,
-            white.clone(),
-        )));
-    }
-
-    objects.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(BvhNode::new_list(boxes2, 0.0, 1.0), 15.0)),
-        Vec3::new(-100.0, 270.0, 395.0),
-    )));
-    objects
-}
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
 
-fn main() {
     let sys_time1 = SystemTime::now();
 
     // get environment variable CI, which is true for GitHub Actions
@@ -434,188 +57,193 @@ fn main() {
 
     println!("CI: {}", is_ci);
 
-    // Image
-    let mut aspect_ratio = 16.0 / 9.0;
-    let mut image_width = 400;
-    let path = "output/test.jpg";
-    let quality = 60; // From 0 to 100, suggested value: 60
-    let mut samples_per_pixel: usize = 100;
-    let max_depth: u8 = 50;
+    // A resumed render continues with the seed it was started with.
+    let resumed = match (&args.state, args.resume) {
+        (Some(state_path), true) => match RenderState::load(Path::new(state_path)) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                eprintln!("error: cannot resume from {}: {}", state_path, e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let seed = args
+        .seed
+        .or(resumed.as_ref().map(|state| state.seed))
+        .unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    // Random scene content and textures are generated from the seed as well.
+    seed_rng(seed);
 
     // World
-    // let world = random_scene();
-    // let bvh = BvhNode::new_list(world, 0.0, 1.0);
-    let mut world = HittableList::new();
-    let mut lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let mut lookat = Point3::new(0.0, 0.0, 0.0);
-    let mut vfov = 40.0;
-    let mut aperture = 0.0;
-    let mut background = Color::zero();
+    let mut scene = match &args.scene {
+        SceneSource::Builtin(name) => Scene::by_name(name).unwrap(),
+        SceneSource::File(path) => match scene_file::load(Path::new(path)) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+    };
+    if let Some(projection) = args.projection {
+        scene.camera.projection = projection;
+    }
 
-    let case = 8;
-    match case {
-        1 => {
-            world = random_scene();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.1;
-        }
-        2 => {
-            world = two_spheres();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        3 => {
-            world = two_perlin_spheres();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        4 => {
-            world = earth();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
+    let mut settings = RenderSettings::for_scene(&scene);
+    if let Some(width) = args.image_width {
+        settings.image_width = width;
+    }
+    let aspect_ratio = args.aspect_ratio.unwrap_or(scene.aspect_ratio);
+    settings.image_height = ((settings.image_width as f64 / aspect_ratio) as usize).max(1);
+    if let Some(spp) = args.samples_per_pixel {
+        settings.samples_per_pixel = spp;
+    }
+    settings.adaptive_threshold = args.adaptive_threshold;
+    if let Some(min_samples) = args.min_samples {
+        settings.min_samples = min_samples;
+    }
+    settings.max_depth = args.max_depth;
+    settings.threads = args.threads;
+    if let Some(tile_size) = args.tile_size {
+        settings.tile_size = tile_size;
+    }
+    settings.progress = !is_ci;
+    settings.checkpoint_interval = args.checkpoint_interval;
+    settings.seed = Some(seed);
+    settings.sampler = args.sampler;
+    settings.filter = args.filter;
+    settings.bvh = args.bvh;
+    settings.integrator = args.integrator;
+    settings.aovs = args.aovs.clone();
+    if args.denoise.is_some() {
+        for aov in denoise::FEATURES {
+            if !settings.aovs.contains(&aov) {
+                settings.aovs.push(aov);
+            }
         }
-        5 => {
-            world = simple_light();
-            samples_per_pixel = 400;
-            background = Color::zero();
-            lookfrom = Point3::new(26.0, 3.0, 6.0);
-            lookat = Point3::new(0.0, 2.0, 0.0);
-            vfov = 20.0;
+    }
+    if let Some(radius) = args.ao_radius {
+        settings.ao_radius = radius;
+    }
+    let path = args.output.as_str();
+
+    let source = match &args.scene {
+        SceneSource::Builtin(name) => name.clone().into_bytes(),
+        SceneSource::File(path) => match std::fs::read(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: cannot read {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+    };
+    // Only a saved state needs the fingerprint, so a scene whose files
+    // cannot all be read still renders without one.
+    let fingerprint = match RenderState::fingerprint(&source, &scene, &settings) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) if args.state.is_some() => {
+            eprintln!(
+                "error: cannot track the scene for {}: {}",
+                args.state.as_deref().unwrap_or_default(),
+                e
+            );
+            std::process::exit(1);
         }
-        6 => {
-            world = cornell_box();
-            aspect_ratio = 1.0;
-            image_width = 600;
-            samples_per_pixel = 200;
-            background = Color::zero();
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        Err(_) => 0,
+    };
+    let mut state = match resumed {
+        Some(state) if state.matches(fingerprint) => {
+            println!("Resuming at {} spp", state.min_samples());
+            state
         }
-        7 => {
-            world = cornell_smoke();
-            aspect_ratio = 1.0;
-            image_width = 600;
-            samples_per_pixel = 200;
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        Some(_) => {
+            eprintln!(
+                "error: cannot resume from {}: it was rendered with a different scene, seed or settings",
+                args.state.as_deref().unwrap_or_default()
+            );
+            std::process::exit(1);
         }
-        8 => {
-            world = final_scene();
-            aspect_ratio = 1.0;
-            image_width = 800;
-            samples_per_pixel = 4000; //10000
-            background = Color::zero();
-            lookfrom = Point3::new(478.0, 278.0, -600.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        None => RenderState::new(
+            settings.image_width,
+            settings.image_height,
+            fingerprint,
+            seed,
+            settings.aovs.len(),
+        ),
+    };
+    let save_state = |state: &RenderState| {
+        if let Some(state_path) = &args.state {
+            if let Err(e) = state.save(Path::new(state_path)) {
+                eprintln!("Saving render state fails: {}", e);
+            }
         }
-        _ => {}
-    }
-    let bvh = BvhNode::new_list(world, 0.0, 1.0);
-
-    // Camera
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let image_height = (image_width as f64 / aspect_ratio) as usize;
-    let cam = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-        0.0,
-        1.0,
-    );
-
-    // Create image data
-    // let mut img: RgbImage = ImageBuffer::new(
-    //     image_width.try_into().unwrap(),
-    //     image_height.try_into().unwrap(),
-    // );
+    };
 
-    // Progress bar UI powered by library `indicatif`
-    // You can use indicatif::ProgressStyle to make it more beautiful
-    // You can also use indicatif::MultiProgress in multi-threading to show progress of each thread
-    let parts = 20;
-    let workers = 7;
-    let bar = if is_ci {
-        ProgressBar::hidden()
-    } else {
-        ProgressBar::new((image_height * image_width) as u64)
+    let output_image = |state: &RenderState| match &args.denoise {
+        Some(denoise) => state.denoised_image(&settings.aovs, denoise),
+        None => state.image(),
     };
 
-    let (sender, receiver) = channel();
-    let pool = ThreadPool::new(workers);
-    for t in 0..parts {
-        let bar0 = bar.clone();
-        let cam0 = cam.clone();
-        let bvh0 = bvh.clone();
-        let sender0 = sender.clone();
-        pool.execute(move || {
-            let begin = image_height * t / parts;
-            let end = image_height * (t + 1) / parts;
-            let mut part_img =
-                ImageBuffer::new(image_width as u32, image_height as u32 / parts as u32);
-            for j in begin..end {
-                for i in 0..image_width {
-                    let mut pixel_color = Color {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 0.0,
-                    };
-                    for _s in 0..samples_per_pixel {
-                        let u = (i as f64 + random_f64()) / (image_width as f64 - 1.0);
-                        let v = (j as f64 + random_f64()) / (image_height as f64 - 1.0);
-                        let r = cam0.get_ray(u, v);
-                        //pixel_color += ray_color(&r, &world, max_depth);
-                        pixel_color += ray_color(&r, &background, &*bvh0, max_depth);
-                    }
-                    write_color(
-                        pixel_color,
-                        &mut part_img,
-                        i,
-                        j - image_height * t / parts,
-                        samples_per_pixel,
-                    );
-                    bar0.inc(1);
-                }
-            }
-            sender0.send((begin..end, part_img)).unwrap();
-        });
-    }
-    let mut img = ImageBuffer::new(image_width as u32, image_height as u32);
-    for (rows, data) in receiver.iter().take(parts) {
-        for (index, row) in rows.enumerate() {
-            for col in 0..image_width {
-                *img.get_pixel_mut(col as u32, (image_height - row - 1) as u32) =
-                    *data.get_pixel(col as u32, index as u32);
-            }
+    // Intermediate images overwrite the output file and are replaced by the
+    // final one.
+    let bvh_stats = render_progressive(&scene, &settings, &mut state, |state| {
+        if let Err(e) = write_image(&output_image(state), Path::new(path), &args.output_settings) {
+            eprintln!(
+                "Writing checkpoint at {} spp fails: {}",
+                state.min_samples(),
+                e
+            );
         }
+        save_state(state);
+    });
+    save_state(&state);
+    if args.bvh_stats {
+        println!("BVH: {}", bvh_stats);
     }
-
-    // Finish progress bar
-    bar.finish();
+    let img = output_image(&state);
 
     // Output image to file
     println!("Ouput image as \"{}\"\nAuthor: {}", path, AUTHOR);
-    let output_image = image::DynamicImage::ImageRgb8(img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("Outputting image fails."),
+    if let Err(e) = write_image(&img, Path::new(path), &args.output_settings) {
+        eprintln!("Outputting image fails: {}", e);
+        std::process::exit(1);
+    }
+    if settings.adaptive_threshold.is_some() {
+        let samples = state.samples.iter().map(|&n| n as u64).sum::<u64>();
+        println!(
+            "Average samples per pixel: {:.1}",
+            samples as f64 / state.samples.len() as f64
+        );
+    }
+    // AOVs hold data rather than radiance, so they skip tone mapping.
+    let aov_settings = OutputSettings {
+        tone_mapping: ToneMapping {
+            transfer: Transfer::Gamma(1.0),
+            ..ToneMapping::default()
+        },
+        ..args.output_settings
+    };
+    for &aov in &args.aovs {
+        let i = settings.aovs.iter().position(|&a| a == aov).unwrap();
+        let aov_path = aov_path(Path::new(path), aov.name());
+        if let Err(e) = write_image(&state.aov_image(i), &aov_path, &aov_settings) {
+            eprintln!("Outputting {} fails: {}", aov_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(heatmap) = &args.heatmap {
+        let heatmap = Path::new(heatmap);
+        let format = OutputFormat::from_path(heatmap).unwrap();
+        if let Err(e) = write_image(
+            &state.sample_heatmap(),
+            heatmap,
+            &OutputSettings::new(format),
+        ) {
+            eprintln!("Outputting heatmap fails: {}", e);
+            std::process::exit(1);
+        }
     }
 
     let sys_time2 = SystemTime::now();
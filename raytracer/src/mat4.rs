@@ -0,0 +1,219 @@
+use crate::rtweekend::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+use std::ops::Mul;
+
+/// A 4x4 matrix acting on column vectors, for affine transforms. `a * b`
+/// applies `b` first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    /// Rows of the matrix.
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::scale(Vec3::ones())
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut t = Self::identity();
+        for i in 0..3 {
+            t.m[i][3] = offset[i];
+        }
+        t
+    }
+
+    /// Scales by `factors` along the axes, which may differ.
+    pub fn scale(factors: Vec3) -> Self {
+        let mut s = Self { m: [[0.0; 4]; 4] };
+        for i in 0..3 {
+            s.m[i][i] = factors[i];
+        }
+        s.m[3][3] = 1.0;
+        s
+    }
+
+    /// Rotates counterclockwise by `angle` degrees about `axis`, seen from
+    /// the tip of the axis.
+    pub fn rotate(axis: Vec3, angle: f64) -> Self {
+        let (sin, cos) = degrees_to_radians(angle / 2.0).sin_cos();
+        let a = axis.unit_vector();
+        Self::from_quaternion(cos, a.x * sin, a.y * sin, a.z * sin)
+    }
+
+    /// The rotation of the quaternion `w + xi + yj + zk`, which is
+    /// normalized first.
+    pub fn from_quaternion(w: f64, x: f64, y: f64, z: f64) -> Self {
+        let n = (w * w + x * x + y * y + z * z).sqrt();
+        let (w, x, y, z) = (w / n, x / n, y / n, z / n);
+        Self::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Shears each axis by the others: `xy` is how much x grows per unit of
+    /// y, and so on.
+    pub fn shear(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Self {
+        Self::new([
+            [1.0, xy, xz, 0.0],
+            [yx, 1.0, yz, 0.0],
+            [zx, zy, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut t = *self;
+        for (i, row) in t.m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        t
+    }
+
+    /// Inverse by Gauss-Jordan elimination; `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        Point3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    /// Applies the matrix without the translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Whether the matrix only rotates, reflects, scales uniformly and
+    /// translates, so that it keeps angles and thus solid angles.
+    pub fn is_similarity(&self) -> bool {
+        let column = |j: usize| Vec3::new(self.m[0][j], self.m[1][j], self.m[2][j]);
+        let (x, y, z) = (column(0), column(1), column(2));
+        let scale = x.length_squared();
+        let tolerance = 1e-9 * scale;
+        (y.length_squared() - scale).abs() < tolerance
+            && (z.length_squared() - scale).abs() < tolerance
+            && x.dot(y).abs() < tolerance
+            && x.dot(z).abs() < tolerance
+            && y.dot(z).abs() < tolerance
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut product = [[0.0; 4]; 4];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self::new(product)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Mat4, b: &Mat4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a.m[i][j] - b.m[i][j]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_matrix() {
+        let m = Mat4::translate(Vec3::new(1.0, -2.0, 3.0))
+            * Mat4::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::shear(0.5, 0.0, 0.0, 0.25, 0.0, 0.0)
+            * Mat4::scale(Vec3::new(2.0, 3.0, 0.5));
+        let inverse = m.inverse().unwrap();
+        assert_close(&(m * inverse), &Mat4::identity());
+        assert_close(&(inverse * m), &Mat4::identity());
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn vectors_ignore_translation() {
+        let m = Mat4::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotate(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        let p = m.transform_point(&Point3::new(1.0, 0.0, 0.0));
+        let v = m.transform_vector(&Vec3::new(1.0, 0.0, 0.0));
+        assert!((p - Point3::new(1.0, 3.0, 3.0)).length() < 1e-9);
+        assert!((v - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn similarity_excludes_nonuniform_scale_and_shear() {
+        assert!(Mat4::rotate(Vec3::new(1.0, 2.0, 3.0), 40.0).is_similarity());
+        assert!(Mat4::scale(Vec3::new(2.0, 2.0, 2.0)).is_similarity());
+        assert!(!Mat4::scale(Vec3::new(2.0, 1.0, 1.0)).is_similarity());
+        assert!(!Mat4::shear(0.5, 0.0, 0.0, 0.0, 0.0, 0.0).is_similarity());
+    }
+}
@@ -1,15 +1,52 @@
 use crate::hittable::HitRecord;
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::rtweekend::random_f64;
+use crate::sampler::{
+    cosine_hemisphere_pdf, sample_cosine_hemisphere, sample_unit_ball, sample_unit_sphere,
+    uniform_sphere_pdf, Sampler,
+};
 use crate::texture::{SolidColor, Texture};
 use crate::vec3::{Color, Point3};
 use crate::Vec3;
 use std::ops::{Mul, Sub};
 use std::sync::Arc;
 
+/// The outcome of scattering a ray off a surface.
+pub struct ScatterRecord {
+    /// Weight of `scattered`, i.e. the BSDF times the cosine divided by `pdf`.
+    pub attenuation: Color,
+    pub scattered: Ray,
+    /// Density of the direction of `scattered`, or `None` for specular
+    /// scattering, which cannot be combined with light sampling.
+    pub pdf: Option<f64>,
+}
+
+/// Decides how light scatters off, or is emitted by, a surface.
+///
+/// Non-specular materials sample directions in proportion to their BSDF
+/// times the cosine, so for any direction that product is `attenuation`
+/// times [`Material::scattering_pdf`].
 pub trait Material: Send + Sync {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    /// Draws the scattered ray with the next dimensions of `sampler`.
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord>;
+
+    /// Density with which [`Material::scatter`] picks the direction of
+    /// `scattered`.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
     fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    /// Fraction of light the surface reflects at `rec`, ignoring direction.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::zero()
+    }
 }
 
 pub struct Lambertian {
@@ -29,15 +66,29 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
-        // Catch degenerate scatter direction
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let local = sample_cosine_hemisphere(sampler.get_2d());
+        let scatter_direction = Onb::build_from_w(&rec.normal).local(&local);
         let scattered = Ray::new(&rec.p, &scatter_direction, r_in.tm);
         let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        Some((attenuation, scattered))
+        Some(ScatterRecord {
+            attenuation,
+            scattered,
+            pdf: Some(cosine_hemisphere_pdf(local.z)),
+        })
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        cosine_hemisphere_pdf(rec.normal.dot(scattered.dir.unit_vector()))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
     }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
@@ -65,21 +116,31 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let reflected = Vec3::reflect(&r_in.dir.unit_vector(), &rec.normal);
-        let scattered = Ray::new(
-            &rec.p,
-            &(reflected + Vec3::random_in_unit_sphere().mul(self.fuzz)),
-            r_in.tm,
-        );
+        let fuzz = sample_unit_ball(sampler.get_2d(), sampler.get_1d());
+        let scattered = Ray::new(&rec.p, &(reflected + fuzz.mul(self.fuzz)), r_in.tm);
         let attenuation = self.albedo;
         if scattered.dir.dot(rec.normal) > 0.0 {
-            Some((attenuation, scattered))
+            Some(ScatterRecord {
+                attenuation,
+                scattered,
+                pdf: None,
+            })
         } else {
             None
         }
     }
 
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color::zero()
     }
@@ -105,7 +166,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if rec.front_face {
             1.0 / self.ir
@@ -121,14 +187,23 @@ impl Material for Dielectric {
         let sin_theta: f64 = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_f64() {
-                Vec3::reflect(&unit_direction, &rec.normal)
-            } else {
-                Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
-            };
+        let direction = if cannot_refract
+            || Self::reflectance(cos_theta, refraction_ratio) > sampler.get_1d()
+        {
+            Vec3::reflect(&unit_direction, &rec.normal)
+        } else {
+            Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
+        };
         let scattered = Ray::new(&rec.p, &direction, r_in.tm);
-        Some((attenuation, scattered))
+        Some(ScatterRecord {
+            attenuation,
+            scattered,
+            pdf: None,
+        })
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
     }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
@@ -141,6 +216,10 @@ pub struct DiffuseLight {
 }
 
 impl DiffuseLight {
+    pub fn new(a: Arc<dyn Texture>) -> Self {
+        Self { emit: a }
+    }
+
     pub fn new_color(c: Color) -> Self {
         Self {
             emit: Arc::new(SolidColor::new(c)),
@@ -149,7 +228,12 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         None
     }
 
@@ -163,9 +247,9 @@ pub struct Isotropic {
 }
 
 impl Isotropic {
-    // pub fn new_texture(a: Arc<dyn Texture>) -> Self {
-    //     Self { albedo: a }
-    // }
+    pub fn new_texture(a: Arc<dyn Texture>) -> Self {
+        Self { albedo: a }
+    }
 
     pub fn new_color(c: Color) -> Self {
         Self {
@@ -175,11 +259,25 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        Some((
-            self.albedo.value(rec.u, rec.v, &rec.p),
-            Ray::new(&rec.p, &Vec3::random_in_unit_sphere(), r_in.tm),
-        ))
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            scattered: Ray::new(&rec.p, &sample_unit_sphere(sampler.get_2d()), r_in.tm),
+            pdf: Some(uniform_sphere_pdf()),
+        })
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        uniform_sphere_pdf()
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
     }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
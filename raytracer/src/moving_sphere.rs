@@ -79,6 +79,10 @@ impl Hittable for MovingSphere {
         let output_box = surrounding_box(box0, box1);
         Some(output_box)
     }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mat_ptr);
+    }
 }
 
 impl MovingSphere {
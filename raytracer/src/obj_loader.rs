@@ -0,0 +1,427 @@
+//! Wavefront OBJ and MTL loading.
+//!
+//! Supports `v`, `vt`, `vn` and `f` (polygons are fan-triangulated, negative
+//! indices count from the end), plus `mtllib`/`usemtl`. MTL materials are
+//! mapped onto the existing [`Material`] implementations:
+//!
+//! * a non-black `Ke` becomes a [`DiffuseLight`],
+//! * `illum` 4, 6, 7 or 9, or `d` below 1, becomes a [`Dielectric`] with `Ni`,
+//! * `illum` 3, or a black `Kd` with a non-black `Ks`, becomes a [`Metal`]
+//!   whose fuzz is derived from the `Ns` exponent,
+//! * anything else is [`Lambertian`] with `map_Kd` or `Kd`.
+
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::texture::{ImageTexture, SolidColor, Texture};
+use crate::triangle::{Triangle, TriangleMesh};
+use crate::vec3::{Color, Point3, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    pub path: PathBuf,
+    /// 1-based line number, or 0 for errors not tied to a line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.path.display(), self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Loads an OBJ file into a single [`TriangleMesh`].
+///
+/// With `material` set, it is used for every face and MTL files are ignored;
+/// otherwise faces use their MTL material, falling back to a light gray
+/// Lambertian.
+pub fn load_obj(
+    path: &Path,
+    material: Option<Arc<dyn Material>>,
+) -> Result<TriangleMesh, ObjError> {
+    load_obj_with_files(path, material).map(|(mesh, _)| mesh)
+}
+
+/// [`load_obj`], which also returns the files it read: the OBJ file, its MTL
+/// files and their textures.
+pub fn load_obj_with_files(
+    path: &Path,
+    material: Option<Arc<dyn Material>>,
+) -> Result<(TriangleMesh, Vec<PathBuf>), ObjError> {
+    let mut files = vec![path.to_path_buf()];
+    let source = read(path)?;
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let error = |line: usize, message: String| ObjError {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+
+    let default_material: Arc<dyn Material> = match &material {
+        Some(m) => m.clone(),
+        None => Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73))),
+    };
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut current = default_material.clone();
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut triangles = Vec::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.split('#').next().unwrap_or_default();
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        match keyword {
+            "v" => positions.push(
+                parse_vec3(&args)
+                    .ok_or_else(|| error(line, String::from("'v' expects three numbers")))?,
+            ),
+            "vn" => normals.push(
+                parse_vec3(&args)
+                    .ok_or_else(|| error(line, String::from("'vn' expects three numbers")))?
+                    .unit_vector(),
+            ),
+            "vt" => {
+                let u = args.first().and_then(|s| s.parse::<f64>().ok());
+                let v = args.get(1).map_or(Some(0.0), |s| s.parse::<f64>().ok());
+                match (u, v) {
+                    (Some(u), Some(v)) => uvs.push((u, v)),
+                    _ => return Err(error(line, String::from("'vt' expects numbers"))),
+                }
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(
+                        line,
+                        String::from("a face needs at least 3 vertices"),
+                    ));
+                }
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in &args {
+                    let corner = parse_corner(arg, positions.len(), uvs.len(), normals.len())
+                        .map_err(|m| error(line, m))?;
+                    corners.push(corner);
+                }
+                // Only use normals and uvs when every corner has them.
+                let has_normals = corners.iter().all(|c| c.1.is_some());
+                let has_uvs = corners.iter().all(|c| c.2.is_some());
+                for k in 1..corners.len() - 1 {
+                    let tri = [corners[0], corners[k], corners[k + 1]];
+                    let mut triangle = Triangle::new(
+                        positions[tri[0].0],
+                        positions[tri[1].0],
+                        positions[tri[2].0],
+                        current.clone(),
+                    );
+                    if has_normals {
+                        triangle.normals = Some(tri.map(|c| normals[c.1.unwrap()]));
+                    }
+                    if has_uvs {
+                        triangle.uvs = Some(tri.map(|c| uvs[c.2.unwrap()]));
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            "mtllib" if material.is_none() => {
+                // File names may contain spaces.
+                let file = base_dir.join(args.join(" "));
+                materials.extend(load_mtl(&file, &mut files)?);
+            }
+            "usemtl" if material.is_none() => {
+                let name = args.join(" ");
+                current = materials
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| error(line, format!("unknown material '{}'", name)))?;
+            }
+            // Object names, groups, smoothing groups and lines are ignored.
+            _ => {}
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err(error(0, String::from("no faces found")));
+    }
+    Ok((TriangleMesh::new(triangles), files))
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|e| ObjError {
+        path: path.to_path_buf(),
+        line: 0,
+        message: e.to_string(),
+    })
+}
+
+fn parse_vec3(args: &[&str]) -> Option<Vec3> {
+    let mut values = args.iter().map(|s| s.parse::<f64>().ok());
+    Some(Vec3::new(values.next()??, values.next()??, values.next()??))
+}
+
+/// Resolves a 1-based (or negative, relative) OBJ index into a 0-based one.
+fn resolve_index(text: &str, count: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = text
+        .parse()
+        .map_err(|_| format!("invalid {} index '{}'", what, text))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{} index {} out of range", what, index));
+    }
+    Ok(resolved as usize)
+}
+
+/// Parses one `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner.
+fn parse_corner(
+    text: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = text.split('/');
+    let v = resolve_index(parts.next().unwrap_or_default(), positions, "vertex")?;
+    let vt = match parts.next() {
+        Some(s) if !s.is_empty() => Some(resolve_index(s, uvs, "texture")?),
+        _ => None,
+    };
+    let vn = match parts.next() {
+        Some(s) if !s.is_empty() => Some(resolve_index(s, normals, "normal")?),
+        _ => None,
+    };
+    Ok((v, vn, vt))
+}
+
+#[derive(Default)]
+struct MtlEntry {
+    kd: Option<Color>,
+    ks: Option<Color>,
+    ke: Option<Color>,
+    ns: Option<f64>,
+    ni: Option<f64>,
+    d: Option<f64>,
+    illum: Option<u32>,
+    map_kd: Option<PathBuf>,
+}
+
+impl MtlEntry {
+    fn build(&self, path: &Path) -> Result<Arc<dyn Material>, ObjError> {
+        let black = |c: Option<Color>| c.is_none_or(|c| c.x + c.y + c.z <= 0.0);
+        if !black(self.ke) {
+            return Ok(Arc::new(DiffuseLight::new_color(self.ke.unwrap())));
+        }
+        if matches!(self.illum, Some(4 | 6 | 7 | 9)) || self.d.is_some_and(|d| d < 1.0) {
+            return Ok(Arc::new(Dielectric::new(self.ni.unwrap_or(1.5))));
+        }
+        if self.illum == Some(3) || (black(self.kd) && !black(self.ks)) {
+            let albedo = self.ks.unwrap_or_else(Color::ones);
+            // Blinn-Phong exponent to roughness.
+            let fuzz = (2.0 / (self.ns.unwrap_or(0.0).max(0.0) + 2.0)).sqrt();
+            return Ok(Arc::new(Metal::new(&albedo, &fuzz)));
+        }
+        let albedo: Arc<dyn Texture> =
+            match &self.map_kd {
+                Some(file) => Arc::new(ImageTexture::open(&file.to_string_lossy()).map_err(
+                    |e| ObjError {
+                        path: path.to_path_buf(),
+                        line: 0,
+                        message: format!("cannot load texture \"{}\": {}", file.display(), e),
+                    },
+                )?),
+                None => Arc::new(SolidColor::new(
+                    self.kd.unwrap_or_else(|| Color::new(0.73, 0.73, 0.73)),
+                )),
+            };
+        Ok(Arc::new(Lambertian::new(albedo)))
+    }
+}
+
+/// Reads the materials of an MTL file and adds it and its textures to
+/// `files`.
+fn load_mtl(
+    path: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    files.push(path.to_path_buf());
+    let source = read(path)?;
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let error = |line: usize, message: String| ObjError {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.split('#').next().unwrap_or_default();
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            entries.push((args.join(" "), MtlEntry::default()));
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => return Err(error(line, format!("'{}' before 'newmtl'", keyword))),
+        };
+        let number = || {
+            args.first()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| error(line, format!("'{}' expects a number", keyword)))
+        };
+        let color = || {
+            parse_vec3(&args)
+                .or_else(|| number().ok().map(|g| Color::new(g, g, g)))
+                .ok_or_else(|| error(line, format!("'{}' expects a color", keyword)))
+        };
+        match keyword {
+            "Kd" => entry.kd = Some(color()?),
+            "Ks" => entry.ks = Some(color()?),
+            "Ke" => entry.ke = Some(color()?),
+            "Ns" => entry.ns = Some(number()?),
+            "Ni" => entry.ni = Some(number()?),
+            "d" => entry.d = Some(number()?),
+            "Tr" => entry.d = Some(1.0 - number()?),
+            "illum" => entry.illum = Some(number()? as u32),
+            // Texture options such as `-bm 1` come before the file name.
+            "map_Kd" => match args.last() {
+                Some(file) => entry.map_kd = Some(base_dir.join(file)),
+                None => return Err(error(line, String::from("'map_Kd' expects a file"))),
+            },
+            _ => {}
+        }
+    }
+
+    let mut materials = HashMap::new();
+    for (name, entry) in entries {
+        files.extend(entry.map_kd.clone());
+        materials.insert(name, entry.build(path)?);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::ray::Ray;
+
+    /// Writes `files` to a fresh directory and returns its path.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("raytracer-obj-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn load(test: &str, obj: &str) -> Result<TriangleMesh, ObjError> {
+        let dir = write_files(test, &[("model.obj", obj)]);
+        load_obj(&dir.join("model.obj"), None)
+    }
+
+    /// The hit of a ray straight down onto the plane z = 0 at (`x`, `y`).
+    fn hit_at(mesh: &TriangleMesh, x: f64, y: f64) -> Option<HitRecord> {
+        let ray = Ray::new(&Point3::new(x, y, 1.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        mesh.hit(&ray, 0.001, f64::INFINITY)
+    }
+
+    #[test]
+    fn faces_use_texture_coordinates_and_normals() {
+        let mesh = load(
+            "attributes",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             vn 0 0 2\n\
+             f 1/1/1 2/2/1 3/3/1 4/4/1\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        let rec = hit_at(&mesh, 0.25, 0.75).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.75).abs() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let positive = load("positive", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let negative = load("negative", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n").unwrap();
+        for (x, y) in [(0.2, 0.2), (0.6, 0.6)] {
+            let expected = hit_at(&positive, x, y).map(|rec| rec.p);
+            assert_eq!(hit_at(&negative, x, y).map(|rec| rec.p), expected);
+        }
+        assert!(hit_at(&negative, 0.2, 0.2).is_some());
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let mesh = load(
+            "pentagon",
+            "v 0 0 0\nv 2 0 0\nv 3 1 0\nv 1 2 0\nv -1 1 0\nf 1 2 3 4 5\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.triangle_count(), 3);
+        // One point inside each of the fan's triangles, and one outside.
+        for (x, y) in [(1.5, 0.3), (1.5, 1.0), (0.2, 1.0)] {
+            assert!(hit_at(&mesh, x, y).is_some(), "({}, {})", x, y);
+        }
+        assert!(hit_at(&mesh, 3.0, 2.0).is_none());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let e = load("range", "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n")
+            .err()
+            .unwrap();
+        assert_eq!(e.line, 4);
+        assert_eq!(e.message, "vertex index 3 out of range");
+        let e = load("short", "v 0 0 0\nf 1 1\n").err().unwrap();
+        assert_eq!(e.line, 2);
+        let e = load("empty", "v 0 0 0\n").err().unwrap();
+        assert_eq!(e.line, 0);
+    }
+
+    #[test]
+    fn mtl_materials_are_mapped() {
+        let dir = write_files(
+            "mtl",
+            &[
+                (
+                    "model.obj",
+                    "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl lamp\nf 1 2 3\n",
+                ),
+                ("model.mtl", "newmtl lamp\nKd 0 0 0\nKe 4 4 4\n"),
+            ],
+        );
+        let (mesh, files) = load_obj_with_files(&dir.join("model.obj"), None).unwrap();
+        let rec = hit_at(&mesh, 0.2, 0.2).unwrap();
+        let emitted = rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
+        assert_eq!(emitted, Color::new(4.0, 4.0, 4.0));
+        assert_eq!(files, [dir.join("model.obj"), dir.join("model.mtl")]);
+    }
+}
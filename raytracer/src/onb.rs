@@ -0,0 +1,64 @@
+use crate::vec3::Vec3;
+use std::ops::Mul;
+
+/// An orthonormal basis, used to turn directions sampled around +z into
+/// directions around an arbitrary axis.
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// A basis whose `w` axis points along `n`, which need not be normalized.
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = n.unit_vector();
+        // Duff et al. 2017, "Building an Orthonormal Basis, Revisited".
+        let sign = 1.0f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        Self {
+            u: Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: Vec3::new(b, sign + w.y * w.y * a, -w.y),
+            w,
+        }
+    }
+
+    /// Converts `a` from local coordinates to world coordinates.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.u.mul(a.x) + self.v.mul(a.y) + self.w.mul(a.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basis_is_orthonormal() {
+        let axes = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -3.0, 0.0),
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(-0.3, 0.2, -5.0),
+            Vec3::new(1e-4, -1e-4, -1.0),
+        ];
+        for n in axes {
+            let onb = Onb::build_from_w(&n);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(a.dot(b).abs() < 1e-12, "{:?}", n);
+            }
+            for axis in [onb.u, onb.v, onb.w] {
+                assert!((axis.length() - 1.0).abs() < 1e-12, "{:?}", n);
+            }
+            // Right-handed, with w along n.
+            assert!((onb.u.cross(onb.v) - onb.w).length() < 1e-12, "{:?}", n);
+            assert!((onb.w - n.unit_vector()).length() < 1e-12, "{:?}", n);
+            let z = onb.local(&Vec3::new(0.0, 0.0, 2.0));
+            assert!((z - onb.w.mul(2.0)).length() < 1e-12, "{:?}", n);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::framebuffer::Image;
+use crate::tonemap::ToneMapping;
+use image::codecs::hdr::HdrEncoder;
+use image::{DynamicImage, ImageOutputFormat, ImageResult};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    /// OpenEXR, 32-bit float linear radiance.
+    Exr,
+    /// Radiance RGBE, linear radiance.
+    Hdr,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "exr" => Some(Self::Exr),
+            "hdr" => Some(Self::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Picks the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_name)
+    }
+
+    /// Whether the format keeps radiance above 1.0.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, Self::Exr | Self::Hdr)
+    }
+}
+
+/// How the rendered radiance is written to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputSettings {
+    pub format: OutputFormat,
+    /// JPEG quality, 1-100.
+    pub quality: u8,
+    /// Only applies to 8-bit formats; EXR and HDR store raw radiance.
+    pub tone_mapping: ToneMapping,
+}
+
+impl OutputSettings {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            quality: 60,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
+
+pub fn write_image(image: &Image, path: &Path, settings: &OutputSettings) -> ImageResult<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let tone_mapping = &settings.tone_mapping;
+    match settings.format {
+        OutputFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8(tone_mapping))
+            .write_to(&mut file, ImageOutputFormat::Jpeg(settings.quality)),
+        OutputFormat::Png => DynamicImage::ImageRgb8(image.to_rgb8(tone_mapping))
+            .write_to(&mut file, ImageOutputFormat::Png),
+        OutputFormat::Exr => DynamicImage::ImageRgb32F(image.to_rgb32f())
+            .write_to(&mut file, ImageOutputFormat::OpenExr),
+        OutputFormat::Hdr => {
+            let pixels: Vec<_> = image.to_rgb32f().pixels().copied().collect();
+            HdrEncoder::new(file).encode(&pixels, image.width, image.height)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+    use image::codecs::hdr::HdrDecoder;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("raytracer-output-{}-{}", std::process::id(), name))
+    }
+
+    /// A 3x2 image spanning several orders of magnitude, above 1.0 too.
+    fn radiance() -> Image {
+        let mut image = Image::new(3, 2);
+        let values = [0.0, 0.015625, 0.25, 1.0, 3.5, 1000.0];
+        for (i, &v) in values.iter().enumerate() {
+            image.set(i % 3, i / 3, Color::new(v, v * 0.5, v * 2.0));
+        }
+        image
+    }
+
+    /// Writes `image` in `format` and reads it back as linear floats.
+    fn round_trip(image: &Image, format: OutputFormat, name: &str) -> Vec<f32> {
+        let path = temp_path(name);
+        write_image(image, &path, &OutputSettings::new(format)).unwrap();
+        let read = match format {
+            // Opened as a generic image, HDR files are converted to 8 bits.
+            OutputFormat::Hdr => {
+                let file = std::io::BufReader::new(File::open(&path).unwrap());
+                let decoder = HdrDecoder::new(file).unwrap();
+                let metadata = decoder.metadata();
+                assert_eq!((metadata.width, metadata.height), (3, 2));
+                let pixels = decoder.read_image_hdr().unwrap();
+                pixels.iter().flat_map(|p| p.0).collect()
+            }
+            _ => {
+                let read = image::open(&path).unwrap().into_rgb32f();
+                assert_eq!((read.width(), read.height()), (3, 2));
+                read.into_raw()
+            }
+        };
+        std::fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn exr_keeps_radiance_exactly() {
+        let image = radiance();
+        let read = round_trip(&image, OutputFormat::Exr, "round-trip.exr");
+        assert_eq!(read, image.to_rgb32f().into_raw());
+    }
+
+    #[test]
+    fn hdr_keeps_radiance_within_rgbe_precision() {
+        let image = radiance();
+        let read = round_trip(&image, OutputFormat::Hdr, "round-trip.hdr");
+        let expected = image.to_rgb32f().into_raw();
+        // RGBE shares one exponent per pixel, so channels keep 8 bits of
+        // precision relative to the brightest one.
+        for (pixel, (read, expected)) in read.chunks(3).zip(expected.chunks(3)).enumerate() {
+            let max = expected.iter().copied().fold(0.0, f32::max);
+            for (r, e) in read.iter().zip(expected) {
+                assert!(
+                    (r - e).abs() <= max / 128.0,
+                    "pixel {}: {} vs {}",
+                    pixel,
+                    r,
+                    e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        let format = |name: &str| OutputFormat::from_path(Path::new(name));
+        assert_eq!(format("out.JPG"), Some(OutputFormat::Jpeg));
+        assert_eq!(format("out.png"), Some(OutputFormat::Png));
+        assert_eq!(format("dir/out.exr"), Some(OutputFormat::Exr));
+        assert_eq!(format("out.hdr"), Some(OutputFormat::Hdr));
+        assert_eq!(format("out.tiff"), None);
+        assert_eq!(format("out"), None);
+    }
+}
@@ -106,3 +106,9 @@ impl Perlin {
         accum.abs()
     }
 }
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
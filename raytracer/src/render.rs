@@ -0,0 +1,472 @@
+use crate::aov::{Aov, MaterialIds};
+use crate::bvh::{BvhNode, BvhSettings, BvhStats};
+use crate::film::{Film, Filter};
+use crate::framebuffer::Image;
+use crate::hittable::Hittable;
+use crate::integrator::IntegratorKind;
+use crate::render_state::RenderState;
+use crate::rtweekend::{hash_seed, seed_rng};
+use crate::sampler::SamplerKind;
+use crate::scene::Scene;
+use crate::tonemap::luminance;
+use crate::vec3::Color;
+use indicatif::ProgressBar;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use threadpool::ThreadPool;
+
+/// Output resolution and sampling parameters for one call to [`render`].
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: usize,
+    /// Maximum number of bounces per path. Russian roulette ends most paths
+    /// well before, so this only guards against endless ones.
+    pub max_depth: u8,
+    pub threads: usize,
+    /// Edge length of the square tiles handed to the worker threads.
+    pub tile_size: usize,
+    /// Draw a progress bar on the terminal while rendering.
+    pub progress: bool,
+    /// Samples per pixel between checkpoints of a progressive render.
+    pub checkpoint_interval: Option<usize>,
+    /// Stop sampling a pixel once the standard error of its luminance falls
+    /// below this fraction of the luminance; `samples_per_pixel` is then the
+    /// upper bound.
+    pub adaptive_threshold: Option<f64>,
+    /// Samples every pixel takes before adaptive sampling may stop it, and
+    /// between error estimates.
+    pub min_samples: usize,
+    /// Seed of a new render; `None` picks one at random. Every sample draws
+    /// its random numbers from a sequence derived from the seed, the pixel
+    /// position and the sample index, so the result does not depend on
+    /// thread scheduling. With the box filter it does not depend on how the
+    /// render was split into passes either; wider filters add the samples
+    /// from neighbouring tiles in a different order, which changes rounding.
+    pub seed: Option<u64>,
+    pub sampler: SamplerKind,
+    /// How the hierarchy over the objects of the world is built.
+    pub bvh: BvhSettings,
+    /// Reconstruction filter that weights samples into nearby pixels.
+    pub filter: Filter,
+    pub integrator: IntegratorKind,
+    /// Distance within which geometry occludes for ambient occlusion.
+    pub ao_radius: f64,
+    /// Extra images to accumulate alongside the rendered one, see
+    /// [`RenderState::aov_image`].
+    pub aovs: Vec<Aov>,
+}
+
+impl RenderSettings {
+    /// Uses the image size and sample count suggested by `scene`.
+    pub fn for_scene(scene: &Scene) -> Self {
+        let image_height = (scene.image_width as f64 / scene.aspect_ratio) as usize;
+        Self {
+            image_width: scene.image_width,
+            image_height: image_height.max(1),
+            samples_per_pixel: scene.samples_per_pixel,
+            max_depth: 50,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            tile_size: 16,
+            progress: false,
+            checkpoint_interval: None,
+            adaptive_threshold: None,
+            min_samples: 16,
+            seed: None,
+            sampler: SamplerKind::Sobol,
+            bvh: BvhSettings::default(),
+            filter: Filter::default(),
+            integrator: IntegratorKind::Path,
+            ao_radius: f64::INFINITY,
+            aovs: Vec::new(),
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+}
+
+/// Renders `scene` on `settings.threads` worker threads into a linear
+/// radiance image.
+pub fn render(scene: &Scene, settings: &RenderSettings) -> Image {
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let mut state = RenderState::new(
+        settings.image_width,
+        settings.image_height,
+        0,
+        seed,
+        settings.aovs.len(),
+    );
+    render_progressive(scene, settings, &mut state, |_| {});
+    state.image()
+}
+
+/// Adds samples to `state` until every pixel has
+/// `settings.samples_per_pixel` of them, or with adaptive sampling, until its
+/// error estimate is small enough. `checkpoint` sees the state every
+/// `settings.checkpoint_interval` samples per pixel, except at the end.
+///
+/// Pass a fresh [`RenderState`] to start a new render, or a loaded one to
+/// resume it. Random numbers are derived from `state.seed`. Returns the
+/// stats of the hierarchy the render traced.
+pub fn render_progressive<F>(
+    scene: &Scene,
+    settings: &RenderSettings,
+    state: &mut RenderState,
+    mut checkpoint: F,
+) -> BvhStats
+where
+    F: FnMut(&RenderState),
+{
+    let image_width = settings.image_width;
+    let image_height = settings.image_height;
+    assert_eq!((state.width, state.height), (image_width, image_height));
+    assert_eq!(state.aovs.len(), settings.aovs.len());
+    let samples_per_pixel = settings.samples_per_pixel as u32;
+    let checkpoint_interval = settings.checkpoint_interval.map(|n| n as u32);
+    let mut pass_size = checkpoint_interval.unwrap_or(samples_per_pixel);
+    if settings.adaptive_threshold.is_some() {
+        pass_size = pass_size.min(settings.min_samples as u32);
+    }
+    let pass_size = pass_size.max(1);
+    let seed = state.seed;
+    let sampler_kind = settings.sampler;
+    let filter = settings.filter;
+    let (bvh, bvh_stats) = build_bvh(scene, settings, seed);
+    // Numbered like the material id view of the debug integrator does.
+    let material_ids = Arc::new(MaterialIds::new(bvh.as_ref()));
+    let integrator = settings.integrator.build(scene, bvh, settings);
+    let background = scene.background;
+    let cam = scene.camera(settings.aspect_ratio());
+
+    // Progress bar UI powered by library `indicatif`
+    // You can use indicatif::ProgressStyle to make it more beautiful
+    // You can also use indicatif::MultiProgress in multi-threading to show progress of each thread
+    let remaining: u64 = state
+        .samples
+        .iter()
+        .map(|&n| samples_per_pixel.saturating_sub(n) as u64)
+        .sum();
+    let bar = if settings.progress {
+        ProgressBar::new(remaining)
+    } else {
+        ProgressBar::hidden()
+    };
+
+    // Tiles are queued on the pool, so idle threads keep picking up the next
+    // one and expensive regions do not hold up the rest of the image.
+    let tiles = tiles(image_width, image_height, settings.tile_size);
+    let pool = ThreadPool::new(settings.threads);
+    let mut budgets = pass_budgets(state, settings, pass_size);
+    let mut since_checkpoint = 0;
+    while budgets.iter().any(|&n| n > 0) {
+        let (sender, receiver) = channel();
+        for (tile_index, tile) in tiles.iter().cloned().enumerate() {
+            let bar0 = bar.clone();
+            let cam0 = cam.clone();
+            let integrator0 = integrator.clone();
+            let aovs = settings.aovs.clone();
+            let material_ids0 = material_ids.clone();
+            let sender0 = sender.clone();
+            // Samples are added onto the running sums in order, so the result
+            // does not depend on how the samples were split into passes.
+            let pixels: Vec<(f64, f64, u32, u32, Vec<Color>)> = tile
+                .pixels()
+                .map(|(x, y)| {
+                    let index = y * image_width + x;
+                    (
+                        state.sum_luminance[index],
+                        state.sum_sq[index],
+                        state.samples[index],
+                        budgets[index],
+                        state.aovs.iter().map(|aov| aov.pixels[index]).collect(),
+                    )
+                })
+                .collect();
+            // The film covers the tile and the pixels around it that its
+            // samples reach. Only the tile starts from the running sums; the
+            // border collects what is added to the neighbouring tiles.
+            let mut film = Film::new(
+                expand(&tile, filter.margin(), image_width, image_height),
+                filter,
+            );
+            for (x, y) in tile.pixels() {
+                let (index, film_index) = (y * image_width + x, film.index(x, y));
+                film.sum[film_index] = state.sum.pixels[index];
+                film.weights[film_index] = state.weights[index];
+            }
+            pool.execute(move || {
+                let mut sampler = sampler_kind.build(seed, samples_per_pixel);
+                let mut tile_img = Vec::with_capacity(pixels.len());
+                for ((x, y), pixel) in tile.pixels().zip(pixels) {
+                    let (mut sum_luminance, mut sum_sq, count, pass_samples, mut aov_sums) = pixel;
+                    // Image rows are stored top first, camera rows bottom first.
+                    let i = x;
+                    let j = image_height - 1 - y;
+                    for s in count..count + pass_samples {
+                        seed_rng(hash_seed(&[seed, x as u64, y as u64, s as u64]));
+                        sampler.start_sample(x, y, s);
+                        let (du, dv) = sampler.get_2d();
+                        let u = (i as f64 + du) / image_width as f64;
+                        let v = (j as f64 + dv) / image_height as f64;
+                        // Samples the camera does not see are black.
+                        let sample = match cam0.get_ray(u, v, &mut *sampler) {
+                            Some(r) => {
+                                let (color, rec) = integrator0.ray_color(&r, &mut *sampler);
+                                for (sum, aov) in aov_sums.iter_mut().zip(&aovs) {
+                                    *sum +=
+                                        aov.value(&r, rec.as_ref(), &background, &material_ids0);
+                                }
+                                color
+                            }
+                            None => Color::zero(),
+                        };
+                        film.add_sample(x, y, (du - 0.5, 0.5 - dv), sample);
+                        sum_luminance += luminance(sample);
+                        sum_sq += luminance(sample) * luminance(sample);
+                    }
+                    tile_img.push((sum_luminance, sum_sq, count + pass_samples, aov_sums));
+                    bar0.inc(pass_samples as u64);
+                }
+                sender0.send((tile_index, film, tile_img)).unwrap();
+            });
+        }
+        // Merge in tile order, so that the sums do not depend on which tile
+        // finished first.
+        let mut results: Vec<_> = receiver.iter().take(tiles.len()).collect();
+        results.sort_by_key(|result| result.0);
+        for (tile_index, film, data) in &results {
+            let tile = tiles[*tile_index];
+            for ((x, y), (sum_luminance, sum_sq, samples, aov_sums)) in tile.pixels().zip(data) {
+                let (index, film_index) = (y * image_width + x, film.index(x, y));
+                state.sum.pixels[index] = film.sum[film_index];
+                state.weights[index] = film.weights[film_index];
+                state.sum_luminance[index] = *sum_luminance;
+                state.sum_sq[index] = *sum_sq;
+                state.samples[index] = *samples;
+                for (aov, sum) in state.aovs.iter_mut().zip(aov_sums) {
+                    aov.pixels[index] = *sum;
+                }
+            }
+        }
+        for (tile_index, film, _) in &results {
+            let tile = tiles[*tile_index];
+            for (x, y) in film.area.pixels() {
+                if (tile.x0..tile.x1).contains(&x) && (tile.y0..tile.y1).contains(&y) {
+                    continue;
+                }
+                let (index, film_index) = (y * image_width + x, film.index(x, y));
+                state.sum.pixels[index] += film.sum[film_index];
+                state.weights[index] += film.weights[film_index];
+            }
+        }
+        budgets = pass_budgets(state, settings, pass_size);
+        since_checkpoint += pass_size;
+        if let Some(interval) = checkpoint_interval {
+            if since_checkpoint >= interval && budgets.iter().any(|&n| n > 0) {
+                checkpoint(state);
+                since_checkpoint = 0;
+            }
+        }
+    }
+
+    // Finish progress bar
+    bar.finish();
+    bvh_stats
+}
+
+/// The hierarchy over the world of `scene` that a render with `seed` traces.
+pub fn build_bvh(
+    scene: &Scene,
+    settings: &RenderSettings,
+    seed: u64,
+) -> (Arc<dyn Hittable>, BvhStats) {
+    // The median split picks random axes.
+    seed_rng(hash_seed(&[seed, u64::MAX]));
+    BvhNode::build(scene.world.objects.clone(), 0.0, 1.0, &settings.bvh)
+}
+
+/// Samples every pixel takes in the next pass: none once it has
+/// `samples_per_pixel`, or has converged under adaptive sampling.
+fn pass_budgets(state: &RenderState, settings: &RenderSettings, pass_size: u32) -> Vec<u32> {
+    let max_samples = settings.samples_per_pixel as u32;
+    let min_samples = (settings.min_samples as u32).min(max_samples);
+    let errors: Vec<f64> = match settings.adaptive_threshold {
+        Some(_) => (0..state.samples.len())
+            .map(|index| state.relative_error(index))
+            .collect(),
+        None => Vec::new(),
+    };
+    let (width, height) = (state.width, state.height);
+    (0..state.samples.len())
+        .map(|index| {
+            let n = state.samples[index];
+            // A few samples can all miss a small light and look converged, so
+            // a pixel only stops once its whole 3x3 neighbourhood has.
+            let converged = settings.adaptive_threshold.is_some_and(|threshold| {
+                let (x, y) = (index % width, index / width);
+                n >= min_samples
+                    && (y.saturating_sub(1)..(y + 2).min(height)).all(|ny| {
+                        (x.saturating_sub(1)..(x + 2).min(width))
+                            .all(|nx| errors[ny * width + nx] < threshold)
+                    })
+            });
+            if converged {
+                0
+            } else {
+                pass_size.min(max_samples.saturating_sub(n))
+            }
+        })
+        .collect()
+}
+
+/// A rectangle of pixels, `x0..x1` by `y0..y1` with rows counted from the top.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    /// Pixel coordinates in row-major order.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let Tile { x0, y0, x1, y1 } = *self;
+        (y0..y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+}
+
+/// `tile` grown by `margin` pixels on every side, clipped to a `width` by
+/// `height` image.
+fn expand(tile: &Tile, margin: usize, width: usize, height: usize) -> Tile {
+    Tile {
+        x0: tile.x0.saturating_sub(margin),
+        y0: tile.y0.saturating_sub(margin),
+        x1: (tile.x1 + margin).min(width),
+        y1: (tile.y1 + margin).min(height),
+    }
+}
+
+/// Covers a `width` by `height` image with `size` by `size` tiles; the tiles
+/// on the right and bottom edges are cut to fit.
+pub fn tiles(width: usize, height: usize, size: usize) -> Vec<Tile> {
+    let size = size.max(1);
+    let mut tiles = Vec::new();
+    for y0 in (0..height).step_by(size) {
+        for x0 in (0..width).step_by(size) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + size).min(width),
+                y1: (y0 + size).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_small(scene: &Scene, threads: usize, tile_size: usize) -> RenderState {
+        let mut settings = RenderSettings::for_scene(scene);
+        settings.image_width = 20;
+        settings.image_height = 15;
+        settings.samples_per_pixel = 3;
+        settings.seed = Some(7);
+        settings.threads = threads;
+        settings.tile_size = tile_size;
+        settings.aovs = Aov::NAMES
+            .iter()
+            .map(|name| Aov::from_name(name).unwrap())
+            .collect();
+        let mut state = RenderState::new(20, 15, 0, 7, settings.aovs.len());
+        render_progressive(scene, &settings, &mut state, |_| {});
+        state
+    }
+
+    #[test]
+    fn renders_do_not_depend_on_threads_or_tiles() {
+        // Build the scene twice, as separate runs would, and keep both alive
+        // so nothing that differs between the builds can go unnoticed.
+        let first = Scene::by_name("cornell_box").unwrap();
+        let second = Scene::by_name("cornell_box").unwrap();
+        let a = render_small(&first, 1, 16);
+        let b = render_small(&second, 3, 7);
+        let bits = |image: &Image| -> Vec<u64> {
+            let channels = image.pixels.iter().flat_map(|c| [c.x, c.y, c.z]);
+            channels.map(f64::to_bits).collect()
+        };
+        assert_eq!(bits(&a.sum), bits(&b.sum));
+        let weights = |state: &RenderState| -> Vec<u64> {
+            state.weights.iter().map(|w| w.to_bits()).collect()
+        };
+        assert_eq!(weights(&a), weights(&b));
+        assert_eq!(a.samples, b.samples);
+        for (i, (x, y)) in a.aovs.iter().zip(&b.aovs).enumerate() {
+            assert_eq!(bits(x), bits(y), "AOV {}", Aov::NAMES[i]);
+        }
+    }
+
+    /// A 5x5 state whose pixels all have `n` samples of luminance 0.5,
+    /// with the settings of an adaptive render of at most 64 samples.
+    fn adaptive_state(n: u32) -> (RenderState, RenderSettings) {
+        let mut state = RenderState::new(5, 5, 0, 1, 0);
+        for index in 0..25 {
+            state.samples[index] = n;
+            state.sum_luminance[index] = 0.5 * n as f64;
+            state.sum_sq[index] = 0.25 * n as f64;
+        }
+        let mut settings = RenderSettings::for_scene(&Scene::by_name("cornell_box").unwrap());
+        settings.samples_per_pixel = 64;
+        settings.min_samples = 16;
+        settings.adaptive_threshold = Some(0.05);
+        (state, settings)
+    }
+
+    #[test]
+    fn converged_pixels_stop_at_min_samples() {
+        let (state, settings) = adaptive_state(8);
+        assert_eq!(pass_budgets(&state, &settings, 8), vec![8; 25]);
+        let (state, settings) = adaptive_state(16);
+        assert_eq!(pass_budgets(&state, &settings, 8), vec![0; 25]);
+    }
+
+    #[test]
+    fn noisy_neighbourhoods_keep_sampling() {
+        let (mut state, settings) = adaptive_state(16);
+        // Pixel (1, 1) alternates between black and white.
+        state.sum_luminance[6] = 8.0;
+        state.sum_sq[6] = 8.0;
+        let budgets = pass_budgets(&state, &settings, 8);
+        for (index, &budget) in budgets.iter().enumerate() {
+            let (x, y) = (index % 5, index / 5);
+            let expected = if x <= 2 && y <= 2 { 8 } else { 0 };
+            assert_eq!(budget, expected, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn budgets_respect_the_sample_cap() {
+        let (mut state, mut settings) = adaptive_state(60);
+        state.sum_luminance[12] = 30.0;
+        state.sum_sq[12] = 30.0;
+        state.samples[13] = 64;
+        let budgets = pass_budgets(&state, &settings, 8);
+        assert_eq!(budgets[12], 4);
+        assert_eq!(budgets[13], 0);
+        assert_eq!(budgets[0], 0);
+
+        // Without adaptive sampling every pixel runs up to the cap.
+        settings.adaptive_threshold = None;
+        let budgets = pass_budgets(&state, &settings, 8);
+        assert_eq!(budgets[0], 4);
+        assert_eq!(budgets[13], 0);
+        state.samples = vec![0; 25];
+        assert_eq!(pass_budgets(&state, &settings, 8), vec![8; 25]);
+    }
+}
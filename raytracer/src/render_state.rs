@@ -0,0 +1,482 @@
+//! Per-pixel sample accumulation that can be saved to disk and resumed.
+//!
+//! The file is a small little-endian binary: the magic `RTSTATE3`, the image
+//! width, height, scene fingerprint, seed and number of AOVs as `u64`, then
+//! for every pixel, top row first, the weighted radiance sum as three `f64`,
+//! the sum of filter weights, the luminance sum and the sum of squared
+//! luminance as `f64`, the sample count as a `u32` and the sum of every AOV as
+//! three `f64`.
+
+use crate::aov::Aov;
+use crate::camera::{Aperture, FieldOfView, Focus};
+use crate::denoise::{denoise, DenoiseSettings};
+use crate::framebuffer::Image;
+use crate::render::RenderSettings;
+use crate::scene::Scene;
+use crate::vec3::Color;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RTSTATE3";
+const MIN_LUMINANCE: f64 = 0.01;
+const HEADER_SIZE: u64 = 48;
+const PIXEL_SIZE: u64 = 52;
+const AOV_SIZE: u64 = 24;
+
+/// Accumulated radiance of a render in progress.
+#[derive(Clone, Debug)]
+pub struct RenderState {
+    pub width: usize,
+    pub height: usize,
+    /// Identifies the scene and settings the samples were taken with, see
+    /// [`RenderState::fingerprint`].
+    pub fingerprint: u64,
+    /// Seed the samples were drawn with, see [`RenderSettings::seed`].
+    pub seed: u64,
+    /// Sum of the samples splatted onto each pixel, weighted by the
+    /// reconstruction filter, see [`RenderSettings::filter`].
+    pub sum: Image,
+    /// Sum of the filter weights per pixel.
+    pub weights: Vec<f64>,
+    /// Sum of the luminance of the samples taken in each pixel, for the
+    /// variance estimate.
+    pub sum_luminance: Vec<f64>,
+    /// Sum of the squared luminance of the samples taken in each pixel.
+    pub sum_sq: Vec<f64>,
+    /// Number of samples per pixel.
+    pub samples: Vec<u32>,
+    /// Sum of all samples per pixel of every AOV in
+    /// [`RenderSettings::aovs`].
+    pub aovs: Vec<Image>,
+}
+
+impl RenderState {
+    pub fn new(width: usize, height: usize, fingerprint: u64, seed: u64, aovs: usize) -> Self {
+        Self {
+            width,
+            height,
+            fingerprint,
+            seed,
+            sum: Image::new(width, height),
+            weights: vec![0.0; width * height],
+            sum_luminance: vec![0.0; width * height],
+            sum_sq: vec![0.0; width * height],
+            samples: vec![0; width * height],
+            aovs: vec![Image::new(width, height); aovs],
+        }
+    }
+
+    /// Hashes everything that changes the value of a sample: `source`, which
+    /// should identify the world (e.g. the contents of a scene file), the
+    /// contents of the files of `scene`, its camera and background, and the
+    /// resolution, depth, seed, sampler, integrator, filter, BVH and AOVs of
+    /// `settings`. The seed is included because it also drives random scene
+    /// content. The sample count is left out so a resumed render can
+    /// continue to more samples. Fails if a file of `scene` cannot be read.
+    pub fn fingerprint(source: &[u8], scene: &Scene, settings: &RenderSettings) -> io::Result<u64> {
+        let mut hasher = Fnv1a::default();
+        source.hash(&mut hasher);
+        for path in &scene.files {
+            let contents = std::fs::read(path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("cannot read \"{}\": {}", path.display(), e),
+                )
+            })?;
+            contents.hash(&mut hasher);
+        }
+        let camera = &scene.camera;
+        for v in [scene.background, camera.lookfrom, camera.lookat, camera.vup] {
+            for a in 0..3 {
+                v[a].to_bits().hash(&mut hasher);
+            }
+        }
+        let (fov_kind, fov) = match camera.fov {
+            FieldOfView::Vertical(degrees) => (0u8, degrees),
+            FieldOfView::Horizontal(degrees) => (1, degrees),
+            FieldOfView::FocalLength(mm) => (2, mm),
+        };
+        let (aperture_kind, aperture) = match camera.aperture {
+            Aperture::Diameter(diameter) => (0u8, diameter),
+            Aperture::FStop(f_number) => (1, f_number),
+        };
+        (camera.projection as u8, fov_kind, aperture_kind).hash(&mut hasher);
+        let focus = match camera.focus {
+            Focus::LookAt => f64::NAN,
+            Focus::Distance(distance) => distance,
+        };
+        for v in [
+            camera.roll,
+            fov,
+            camera.sensor_width,
+            aperture,
+            focus,
+            camera.unit_mm,
+            camera.time0,
+            camera.time1,
+        ] {
+            v.to_bits().hash(&mut hasher);
+        }
+        (settings.image_width as u64).hash(&mut hasher);
+        (settings.image_height as u64).hash(&mut hasher);
+        settings.max_depth.hash(&mut hasher);
+        settings.seed.hash(&mut hasher);
+        (settings.sampler as u8).hash(&mut hasher);
+        (settings.integrator as u8).hash(&mut hasher);
+        settings.ao_radius.to_bits().hash(&mut hasher);
+        (settings.filter.kind as u8).hash(&mut hasher);
+        settings.filter.radius.to_bits().hash(&mut hasher);
+        // The hierarchy only changes which of two equally close hits is
+        // found, but the BVH cost view shows it.
+        (settings.bvh.split as u8).hash(&mut hasher);
+        (settings.bvh.layout as u8).hash(&mut hasher);
+        (settings.bvh.bins as u64).hash(&mut hasher);
+        (settings.bvh.max_leaf_size as u64).hash(&mut hasher);
+        for aov in &settings.aovs {
+            (*aov as u8).hash(&mut hasher);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Whether the samples were taken with the scene and settings that hash
+    /// to `fingerprint`, so the render can be resumed with them.
+    pub fn matches(&self, fingerprint: u64) -> bool {
+        self.fingerprint == fingerprint
+    }
+
+    /// Smallest number of samples taken by any pixel.
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    /// The filtered image: every pixel's weighted sum over its weights.
+    pub fn image(&self) -> Image {
+        let mut img = self.sum.clone();
+        for (c, &w) in img.pixels.iter_mut().zip(&self.weights) {
+            if w > 0.0 {
+                *c /= w;
+            }
+        }
+        img
+    }
+
+    /// [`RenderState::image`] with the noise filtered out. `aovs` lists the
+    /// AOVs of the state, which must include
+    /// [`denoise::FEATURES`](crate::denoise::FEATURES).
+    pub fn denoised_image(&self, aovs: &[Aov], settings: &DenoiseSettings) -> Image {
+        let feature = |aov| {
+            let index = aovs.iter().position(|&a| a == aov);
+            self.aov_image(index.expect("missing AOV for denoising"))
+        };
+        let variance: Vec<f64> = (0..self.samples.len()).map(|i| self.variance(i)).collect();
+        denoise(
+            &self.image(),
+            &variance,
+            &feature(Aov::Albedo),
+            &feature(Aov::Normal),
+            &feature(Aov::Emission),
+            settings,
+        )
+    }
+
+    /// The running average of AOV number `index`.
+    pub fn aov_image(&self, index: usize) -> Image {
+        self.average(&self.aovs[index])
+    }
+
+    fn average(&self, sum: &Image) -> Image {
+        let mut img = sum.clone();
+        for (c, &n) in img.pixels.iter_mut().zip(&self.samples) {
+            // Divide the color by the number of samples.
+            if n > 0 {
+                *c /= n as f64;
+            }
+        }
+        img
+    }
+
+    /// Variance of the mean luminance of pixel `index`, i.e. the squared
+    /// standard error; infinite until the pixel has two samples.
+    pub fn variance(&self, index: usize) -> f64 {
+        let n = self.samples[index] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let mean = self.sum_luminance[index] / n;
+        (self.sum_sq[index] / n - mean * mean).max(0.0) / (n - 1.0)
+    }
+
+    /// Standard error of the mean luminance of pixel `index`, relative to
+    /// that luminance; infinite until the pixel has two samples.
+    pub fn relative_error(&self, index: usize) -> f64 {
+        let mean = self.sum_luminance[index] / self.samples[index].max(1) as f64;
+        // Keep near-black pixels from demanding a tiny absolute error.
+        self.variance(index).sqrt() / mean.max(MIN_LUMINANCE)
+    }
+
+    /// Sample count per pixel as a color ramp from blue (fewest samples)
+    /// through green to red (most samples).
+    pub fn sample_heatmap(&self) -> Image {
+        let max = self.samples.iter().copied().max().unwrap_or(0).max(1) as f64;
+        let mut img = Image::new(self.width, self.height);
+        for (c, &n) in img.pixels.iter_mut().zip(&self.samples) {
+            let t = n as f64 / max;
+            *c = Color::new(
+                (2.0 * t - 1.0).max(0.0),
+                1.0 - (2.0 * t - 1.0).abs(),
+                (1.0 - 2.0 * t).max(0.0),
+            );
+        }
+        img
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 8];
+        if length < HEADER_SIZE || reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Err(invalid_data("not a render state file"));
+        }
+        let width = read_u64(&mut reader)? as usize;
+        let height = read_u64(&mut reader)? as usize;
+        let fingerprint = read_u64(&mut reader)?;
+        let seed = read_u64(&mut reader)?;
+        let aovs = read_u64(&mut reader)? as usize;
+        let pixel_size = (aovs as u64)
+            .checked_mul(AOV_SIZE)
+            .and_then(|n| n.checked_add(PIXEL_SIZE));
+        let pixels = width
+            .checked_mul(height)
+            .zip(pixel_size)
+            .filter(|&(n, size)| {
+                (n as u64)
+                    .checked_mul(size)
+                    .and_then(|n| n.checked_add(HEADER_SIZE))
+                    == Some(length)
+            })
+            .ok_or_else(|| invalid_data("file size does not match the image size"))?
+            .0;
+        let mut state = Self::new(width, height, fingerprint, seed, aovs);
+        for i in 0..pixels {
+            let x = read_f64(&mut reader)?;
+            let y = read_f64(&mut reader)?;
+            let z = read_f64(&mut reader)?;
+            state.sum.pixels[i] = Color::new(x, y, z);
+            state.weights[i] = read_f64(&mut reader)?;
+            state.sum_luminance[i] = read_f64(&mut reader)?;
+            state.sum_sq[i] = read_f64(&mut reader)?;
+            let mut count = [0; 4];
+            reader.read_exact(&mut count)?;
+            state.samples[i] = u32::from_le_bytes(count);
+            for aov in &mut state.aovs {
+                let x = read_f64(&mut reader)?;
+                let y = read_f64(&mut reader)?;
+                let z = read_f64(&mut reader)?;
+                aov.pixels[i] = Color::new(x, y, z);
+            }
+        }
+        Ok(state)
+    }
+
+    /// Writes the state next to `path` first and then renames it over `path`,
+    /// so an interrupted save keeps the previous state intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temp)?);
+            writer.write_all(MAGIC)?;
+            let header = [
+                self.width as u64,
+                self.height as u64,
+                self.fingerprint,
+                self.seed,
+                self.aovs.len() as u64,
+            ];
+            for v in header {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            for (i, c) in self.sum.pixels.iter().enumerate() {
+                let (w, l, sq) = (self.weights[i], self.sum_luminance[i], self.sum_sq[i]);
+                for v in [c.x, c.y, c.z, w, l, sq] {
+                    writer.write_all(&v.to_le_bytes())?;
+                }
+                writer.write_all(&self.samples[i].to_le_bytes())?;
+                for aov in &self.aovs {
+                    let c = aov.pixels[i];
+                    for v in [c.x, c.y, c.z] {
+                        writer.write_all(&v.to_le_bytes())?;
+                    }
+                }
+            }
+            writer.into_inner()?.sync_all()?;
+        }
+        std::fs::rename(&temp, path)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+/// 64-bit FNV-1a; unlike `DefaultHasher` its output is stable across Rust
+/// releases, which matters for fingerprints stored on disk.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-state-{}-{}", std::process::id(), test))
+    }
+
+    fn filled_state() -> RenderState {
+        let mut state = RenderState::new(3, 2, 0x1234_5678_9abc_def0, 42, 2);
+        for i in 0..6 {
+            let v = i as f64;
+            state.sum.pixels[i] = Color::new(v, 0.5 * v, -v);
+            state.weights[i] = 1.0 + v;
+            state.sum_luminance[i] = 0.25 * v;
+            state.sum_sq[i] = v * v;
+            state.samples[i] = 10 + i as u32;
+            state.aovs[0].pixels[i] = Color::new(v, v, v);
+            state.aovs[1].pixels[i] = Color::new(-v, f64::INFINITY, 1e-300);
+        }
+        state
+    }
+
+    #[test]
+    fn saved_state_loads_unchanged() {
+        let path = temp_path("round-trip");
+        let state = filled_state();
+        state.save(&path).unwrap();
+        let loaded = RenderState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.fingerprint, state.fingerprint);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.sum.pixels, state.sum.pixels);
+        assert_eq!(loaded.weights, state.weights);
+        assert_eq!(loaded.sum_luminance, state.sum_luminance);
+        assert_eq!(loaded.sum_sq, state.sum_sq);
+        assert_eq!(loaded.samples, state.samples);
+        assert_eq!(loaded.aovs.len(), 2);
+        for (a, b) in loaded.aovs.iter().zip(&state.aovs) {
+            assert_eq!(a.pixels, b.pixels);
+        }
+    }
+
+    #[test]
+    fn changed_width_or_scene_is_rejected() {
+        let scene = Scene::by_name("cornell_box").unwrap();
+        let mut settings = RenderSettings::for_scene(&scene);
+        settings.seed = Some(1);
+        let fingerprint = |source: &[u8], scene: &Scene, settings: &RenderSettings| {
+            RenderState::fingerprint(source, scene, settings).unwrap()
+        };
+        let path = temp_path("fingerprint");
+        RenderState::new(
+            settings.image_width,
+            settings.image_height,
+            fingerprint(b"cornell_box", &scene, &settings),
+            1,
+            0,
+        )
+        .save(&path)
+        .unwrap();
+        let loaded = RenderState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.matches(fingerprint(b"cornell_box", &scene, &settings)));
+
+        let mut wider = settings.clone();
+        wider.image_width += 1;
+        assert!(!loaded.matches(fingerprint(b"cornell_box", &scene, &wider)));
+        assert!(!loaded.matches(fingerprint(b"cornell_smoke", &scene, &settings)));
+        let mut moved = Scene::by_name("cornell_box").unwrap();
+        moved.camera.lookfrom[0] += 1.0;
+        assert!(!loaded.matches(fingerprint(b"cornell_box", &moved, &settings)));
+    }
+
+    #[test]
+    fn fingerprint_covers_the_scene_files() {
+        let mut scene = Scene::by_name("cornell_box").unwrap();
+        let settings = RenderSettings::for_scene(&scene);
+        let model = temp_path("model.obj");
+        scene.files.push(model.clone());
+        std::fs::write(&model, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let before = RenderState::fingerprint(b"scene", &scene, &settings).unwrap();
+        assert_eq!(
+            RenderState::fingerprint(b"scene", &scene, &settings).unwrap(),
+            before
+        );
+        std::fs::write(&model, "v 0 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let after = RenderState::fingerprint(b"scene", &scene, &settings).unwrap();
+        assert_ne!(after, before);
+        std::fs::remove_file(&model).unwrap();
+        let e = RenderState::fingerprint(b"scene", &scene, &settings).unwrap_err();
+        assert!(e.to_string().contains("model.obj"), "{}", e);
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_are_rejected() {
+        let path = temp_path("corrupt");
+        filled_state().save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        for length in [0, 4, HEADER_SIZE as usize - 1, bytes.len() - 1] {
+            std::fs::write(&path, &bytes[..length]).unwrap();
+            let e = RenderState::load(&path).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "length {}", length);
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        std::fs::write(&path, &longer).unwrap();
+        assert_eq!(
+            RenderState::load(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut foreign = bytes.clone();
+        foreign[..8].copy_from_slice(b"RTSTATE2");
+        std::fs::write(&path, &foreign).unwrap();
+        assert_eq!(
+            RenderState::load(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
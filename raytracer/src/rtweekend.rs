@@ -1,11 +1,37 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    // Every thread has its own generator, seeded from the OS until
+    // `seed_rng` is called.
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Restarts the current thread's random sequence from `seed`.
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Combines `values` into a single well-mixed seed.
+pub fn hash_seed(values: &[u64]) -> u64 {
+    // SplitMix64 finalizer applied to a running state.
+    let mut h = 0x9e37_79b9_7f4a_7c15u64;
+    for &v in values {
+        h = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+    h
+}
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * std::f64::consts::PI / 180.0
 }
 
 pub fn random_f64() -> f64 {
-    let rnd = rand::thread_rng().gen_range(0.0..f64::MAX);
+    let rnd = RNG.with(|rng| rng.borrow_mut().gen_range(0.0..f64::MAX));
     rnd / (f64::MAX + 1.0)
 }
 
@@ -14,5 +40,18 @@ pub fn random_f64_range(min: f64, max: f64) -> f64 {
 }
 
 pub fn random_usize_range(min: usize, max: usize) -> usize {
-    rand::thread_rng().gen_range(min..max + 1)
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max + 1))
+}
+
+/// Parses an aspect ratio written either as a number or as `W:H`.
+pub fn parse_aspect_ratio(value: &str) -> Option<f64> {
+    let ratio = match value.split_once(':') {
+        Some((w, h)) => w.parse::<f64>().ok()? / h.parse::<f64>().ok()?,
+        None => value.parse::<f64>().ok()?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Some(ratio)
+    } else {
+        None
+    }
 }
@@ -0,0 +1,598 @@
+//! Sample generators for pixel, lens, time and scattering dimensions.
+//!
+//! A [`Sampler`] is restarted for every sample of every pixel with
+//! [`Sampler::start_sample`] and then hands out the dimensions of that sample
+//! one after the other. The low-discrepancy samplers spread the samples of one
+//! pixel evenly over each dimension, which converges faster than independent
+//! random numbers.
+
+use crate::rtweekend::{hash_seed, random_f64};
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+use std::ops::Mul;
+
+pub trait Sampler {
+    /// Prepares sample number `index` of pixel (`x`, `y`).
+    fn start_sample(&mut self, x: usize, y: usize, index: u32);
+    /// The next dimension of the current sample, in `[0, 1)`.
+    fn get_1d(&mut self) -> f64;
+    /// The next two dimensions of the current sample, in `[0, 1)`.
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    Independent,
+    /// Jittered strata, shuffled independently for every dimension.
+    Stratified,
+    /// Randomly rotated Halton sequence.
+    Halton,
+    /// Owen-scrambled Sobol sequence, padded with independently shuffled
+    /// copies for dimensions beyond the second.
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const NAMES: [&'static str; 4] = ["independent", "stratified", "halton", "sobol"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "independent" => Some(Self::Independent),
+            "stratified" => Some(Self::Stratified),
+            "halton" => Some(Self::Halton),
+            "sobol" => Some(Self::Sobol),
+            _ => None,
+        }
+    }
+
+    /// A sampler for renders with `samples_per_pixel` samples, deriving its
+    /// scrambling from `seed`.
+    pub fn build(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            Self::Independent => Box::new(IndependentSampler),
+            Self::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            Self::Halton => Box::new(HaltonSampler::new(seed)),
+            Self::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+/// Uniform random numbers from the thread's generator.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _x: usize, _y: usize, _index: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        random_f64()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (random_f64(), random_f64())
+    }
+}
+
+/// Splits every dimension into `samples_per_pixel` strata (2D dimensions
+/// into a grid of about that many cells) and gives each sample of a pixel its
+/// own, randomly jittered stratum.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    // Grid used for 2D dimensions.
+    nx: u32,
+    ny: u32,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        let n = samples_per_pixel.max(1);
+        let nx = (n as f64).sqrt().ceil() as u32;
+        Self {
+            seed,
+            samples_per_pixel: n,
+            nx,
+            ny: n.div_ceil(nx),
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// The stratum of the current sample among `n`, and a seed for jittering
+    /// within it. Samples past `n`, e.g. after resuming with more samples,
+    /// start another round of strata.
+    fn stratum(&mut self, n: u32) -> (u32, u64) {
+        let round = self.index / n;
+        let key = hash_seed(&[self.pixel_seed, self.dimension, round as u64]);
+        self.dimension += 1;
+        let jitter_seed = hash_seed(&[key, self.index as u64]);
+        (permute(self.index % n, n, key as u32), jitter_seed)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.pixel_seed = hash_seed(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let (stratum, jitter) = self.stratum(n);
+        (stratum as f64 + to_unit(jitter)) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (nx, ny) = (self.nx, self.ny);
+        let (stratum, jitter) = self.stratum(nx * ny);
+        let jx = to_unit(jitter);
+        let jy = to_unit(hash_seed(&[jitter]));
+        (
+            ((stratum % nx) as f64 + jx) / nx as f64,
+            ((stratum / nx) as f64 + jy) / ny as f64,
+        )
+    }
+}
+
+/// The Halton sequence, one prime base per dimension, with a random toroidal
+/// shift per pixel and dimension. Dimensions past the table of primes fall
+/// back to independent random numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    primes: Vec<u32>,
+    pixel_seed: u64,
+    index: u32,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    const DIMENSIONS: usize = 128;
+
+    pub fn new(seed: u64) -> Self {
+        let mut primes: Vec<u32> = Vec::with_capacity(Self::DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < Self::DIMENSIONS {
+            if primes.iter().all(|p| candidate % p != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+        Self {
+            seed,
+            primes,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.pixel_seed = hash_seed(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.primes.get(dimension) {
+            Some(&base) => {
+                let shift = to_unit(hash_seed(&[self.pixel_seed, dimension as u64]));
+                let value = radical_inverse(self.index, base) + shift;
+                value - value.floor()
+            }
+            None => random_f64(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// The first two Sobol dimensions with Owen scrambling (Burley 2020,
+/// "Practical Hash-based Owen Scrambling"). Every further pair of dimensions
+/// reuses them with a differently scrambled sample order, so the pairs stay
+/// uncorrelated.
+pub struct SobolSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next_seed(&mut self) -> u32 {
+        let seed = hash_seed(&[self.pixel_seed, self.dimension]) as u32;
+        self.dimension += 1;
+        seed
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.pixel_seed = hash_seed(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = self.next_seed();
+        let index = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_u32(seed));
+        x as f64 / 4294967296.0
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = self.next_seed();
+        let index = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_u32(seed));
+        let y = nested_uniform_scramble(sobol_second(index), hash_u32(seed ^ 0x5bd1_e995));
+        (x as f64 / 4294967296.0, y as f64 / 4294967296.0)
+    }
+}
+
+/// Maps the top 53 bits of `bits` to `[0, 1)`.
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn hash_u32(x: u32) -> u32 {
+    hash_seed(&[x as u64]) as u32
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut value = 0.0;
+    while index > 0 {
+        value += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    value
+}
+
+/// The second Sobol dimension, generated by the polynomial `x + 1`.
+fn sobol_second(mut index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// Owen scrambling of the bits of `x`, most significant first.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// A pseudo-random permutation of `0..n`, picked by `seed` (Kensler 2013,
+/// "Correlated Multi-Jittered Sampling").
+fn permute(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % n
+}
+
+/// A uniformly distributed direction, see [`uniform_sphere_pdf`].
+pub fn sample_unit_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// A uniformly distributed point inside the unit ball.
+pub fn sample_unit_ball(u: (f64, f64), w: f64) -> Vec3 {
+    sample_unit_sphere(u).mul(w.cbrt())
+}
+
+/// A uniformly distributed point on the unit disk in the xy plane, using
+/// Shirley's concentric mapping to keep strata compact.
+pub fn sample_unit_disk(u: (f64, f64)) -> Vec3 {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3::zero();
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Density of [`sample_unit_sphere`] per unit solid angle.
+pub fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+/// A direction in the hemisphere around +z with density proportional to the
+/// cosine of its angle to +z, see [`cosine_hemisphere_pdf`].
+pub fn sample_cosine_hemisphere(u: (f64, f64)) -> Vec3 {
+    let d = sample_unit_disk(u);
+    let z = (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt();
+    Vec3::new(d.x, d.y, z)
+}
+
+/// Density of [`sample_cosine_hemisphere`] for a direction whose angle to
+/// the axis has cosine `cos_theta`.
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) / PI
+}
+
+/// A uniformly distributed direction within the cone around +z whose
+/// half-angle has cosine `cos_theta_max`, see [`cone_pdf`].
+pub fn sample_cone(u: (f64, f64), cos_theta_max: f64) -> Vec3 {
+    let z = 1.0 - u.0 * (1.0 - cos_theta_max);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Density of [`sample_cone`] per unit solid angle.
+pub fn cone_pdf(cos_theta_max: f64) -> f64 {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::seed_rng;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    /// The first `dimensions` values of samples `0..samples` of pixel
+    /// (`x`, `y`), taking the dimensions in pairs.
+    fn pixel_values(
+        sampler: &mut dyn Sampler,
+        x: usize,
+        y: usize,
+        samples: u32,
+        dimensions: usize,
+    ) -> Vec<f64> {
+        let mut values = Vec::new();
+        for index in 0..samples {
+            sampler.start_sample(x, y, index);
+            for _ in 0..dimensions / 2 {
+                let (u, v) = sampler.get_2d();
+                values.push(u);
+                values.push(v);
+            }
+            values.push(sampler.get_1d());
+        }
+        values
+    }
+
+    #[test]
+    fn values_are_in_the_unit_interval() {
+        for kind in KINDS {
+            let mut sampler = kind.build(3, 16);
+            for (x, y) in [(0, 0), (5, 9), (1000, 2000)] {
+                for value in pixel_values(sampler.as_mut(), x, y, 64, 140) {
+                    assert!((0.0..1.0).contains(&value), "{:?} gave {}", kind, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_repeat_for_a_seed() {
+        for kind in KINDS {
+            let mut first = kind.build(11, 8);
+            let mut second = kind.build(11, 8);
+            let mut other = kind.build(12, 8);
+            seed_rng(11);
+            let a = pixel_values(first.as_mut(), 4, 2, 8, 6);
+            seed_rng(11);
+            let b = pixel_values(second.as_mut(), 4, 2, 8, 6);
+            seed_rng(12);
+            let c = pixel_values(other.as_mut(), 4, 2, 8, 6);
+            assert_eq!(a, b, "{:?}", kind);
+            assert_ne!(a, c, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum_once() {
+        for n in [16, 10] {
+            let mut sampler = StratifiedSampler::new(5, n);
+            let (nx, ny) = (sampler.nx, sampler.ny);
+            for dimension in 0..4 {
+                let mut strata = vec![0; n as usize];
+                let mut cells = vec![0; (nx * ny) as usize];
+                for index in 0..n {
+                    sampler.start_sample(7, 3, index);
+                    for _ in 0..dimension {
+                        sampler.get_1d();
+                    }
+                    strata[(sampler.get_1d() * n as f64) as usize] += 1;
+                    let (u, v) = sampler.get_2d();
+                    let cell = (v * ny as f64) as u32 * nx + (u * nx as f64) as u32;
+                    cells[cell as usize] += 1;
+                }
+                assert!(strata.iter().all(|&count| count == 1), "{:?}", strata);
+                assert!(cells.iter().all(|&count| count <= 1), "{:?}", cells);
+                assert_eq!(cells.iter().sum::<u32>(), n);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_samples_are_stratified() {
+        let mut sampler = SobolSampler::new(9);
+        let mut strata = [0; 16];
+        let mut cells = [0; 16];
+        for index in 0..16 {
+            sampler.start_sample(2, 8, index);
+            strata[(sampler.get_1d() * 16.0) as usize] += 1;
+            let (u, v) = sampler.get_2d();
+            cells[(v * 4.0) as usize * 4 + (u * 4.0) as usize] += 1;
+        }
+        assert_eq!(strata, [1; 16]);
+        assert_eq!(cells, [1; 16]);
+    }
+
+    #[test]
+    fn sequences_start_with_the_reference_points() {
+        let halton_2 = [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875];
+        let halton_3 = [0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0, 7.0 / 9.0];
+        let sobol_2 = [0.0, 0.5, 0.75, 0.25, 0.625, 0.125, 0.375, 0.875];
+        for (index, &expected) in halton_2.iter().enumerate() {
+            assert_eq!(radical_inverse(index as u32, 2), expected);
+            let sobol_1 = index as u32;
+            assert_eq!(sobol_1.reverse_bits() as f64 / 4294967296.0, expected);
+        }
+        for (index, &expected) in halton_3.iter().enumerate() {
+            assert!((radical_inverse(index as u32, 3) - expected).abs() < 1e-12);
+        }
+        for (index, &expected) in sobol_2.iter().enumerate() {
+            assert_eq!(sobol_second(index as u32) as f64 / 4294967296.0, expected);
+        }
+
+        // The Halton sampler shifts the sequence by the same amount for every
+        // sample of a pixel.
+        let mut sampler = HaltonSampler::new(4);
+        sampler.start_sample(1, 1, 0);
+        let shift = sampler.get_1d();
+        for (index, &expected) in halton_2.iter().enumerate() {
+            sampler.start_sample(1, 1, index as u32);
+            let value = sampler.get_1d() - shift;
+            assert!((value - value.floor() - expected).abs() < 1e-12);
+        }
+    }
+
+    /// The integral of `f` over all directions, from a grid of uniformly
+    /// distributed directions.
+    fn integrate_sphere(f: impl Fn(Vec3) -> f64) -> f64 {
+        let (nz, nphi) = (4096, 64);
+        let mut sum = 0.0;
+        for i in 0..nz {
+            for j in 0..nphi {
+                let u = ((i as f64 + 0.5) / nz as f64, (j as f64 + 0.5) / nphi as f64);
+                sum += f(sample_unit_sphere(u));
+            }
+        }
+        sum / (nz * nphi) as f64 / uniform_sphere_pdf()
+    }
+
+    /// The mean of `f` over directions from `sample`, on a grid of `u`.
+    fn mean_of_samples(sample: impl Fn((f64, f64)) -> Vec3, f: impl Fn(Vec3) -> f64) -> f64 {
+        let n = 256;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let d = sample(((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64));
+                assert!((d.length() - 1.0).abs() < 1e-9);
+                sum += f(d);
+            }
+        }
+        sum / (n * n) as f64
+    }
+
+    #[test]
+    fn pdfs_integrate_to_one() {
+        let sphere = integrate_sphere(|_| uniform_sphere_pdf());
+        assert!((sphere - 1.0).abs() < 1e-9, "{}", sphere);
+        let cosine = integrate_sphere(|d| cosine_hemisphere_pdf(d.z));
+        assert!((cosine - 1.0).abs() < 1e-3, "{}", cosine);
+        for cos_theta_max in [0.0, 0.5, 0.95] {
+            let cone = integrate_sphere(|d| {
+                if d.z >= cos_theta_max {
+                    cone_pdf(cos_theta_max)
+                } else {
+                    0.0
+                }
+            });
+            assert!((cone - 1.0).abs() < 0.01, "{}: {}", cos_theta_max, cone);
+        }
+    }
+
+    #[test]
+    fn samples_follow_their_pdfs() {
+        // The mean of cos(theta) is the integral of cos(theta) * pdf.
+        let sphere = mean_of_samples(sample_unit_sphere, |d| d.z.abs());
+        assert!((sphere - 0.5).abs() < 1e-3, "{}", sphere);
+        let cosine = mean_of_samples(sample_cosine_hemisphere, |d| d.z);
+        assert!((cosine - 2.0 / 3.0).abs() < 1e-3, "{}", cosine);
+        for cos_theta_max in [0.0, 0.5, 0.95] {
+            let cone = mean_of_samples(
+                |u| sample_cone(u, cos_theta_max),
+                |d| {
+                    assert!(d.z >= cos_theta_max - 1e-12);
+                    d.z
+                },
+            );
+            let expected = (1.0 + cos_theta_max) / 2.0;
+            assert!(
+                (cone - expected).abs() < 1e-3,
+                "{}: {}",
+                cos_theta_max,
+                cone
+            );
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::box_object::BoxObject;
+use crate::bvh::BvhNode;
+use crate::camera::{CameraConfig, CameraModel};
+use crate::constant_medium::ConstantMedium;
+use crate::hittable::{Hittable, RotateY, Translate};
+use crate::hittable_list::HittableList;
+use crate::instance::Instance;
+use crate::mat4::Mat4;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::moving_sphere::MovingSphere;
+use crate::rtweekend::{random_f64, random_f64_range};
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture};
+use crate::vec3::{Color, Point3, Vec3};
+use std::ops::Sub;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Names accepted by [`Scene::by_name`].
+pub const SCENE_NAMES: [&str; 8] = [
+    "random_scene",
+    "two_spheres",
+    "two_perlin_spheres",
+    "earth",
+    "simple_light",
+    "cornell_box",
+    "cornell_smoke",
+    "final_scene",
+];
+
+/// Texture of the earth scenes, relative to the working directory.
+const EARTH_MAP: &str = "earthmap.jpg";
+
+/// A world together with the camera and background it is meant to be viewed with.
+pub struct Scene {
+    pub world: HittableList,
+    /// Emitters to sample directly. Each one must also be in `world` and
+    /// implement [`Hittable::pdf_value`] and [`Hittable::random`].
+    pub lights: HittableList,
+    pub background: Color,
+    pub camera: CameraConfig,
+    // Suggested image settings, see `RenderSettings::for_scene`.
+    pub aspect_ratio: f64,
+    pub image_width: usize,
+    pub samples_per_pixel: usize,
+    /// Files the world was built from, such as models and textures, which a
+    /// resumed render must find unchanged.
+    pub files: Vec<PathBuf>,
+}
+
+impl Scene {
+    /// Wraps `world` with the default camera, a black background and a
+    /// 400px wide 16:9 image at 100 samples per pixel.
+    pub fn new(world: HittableList) -> Self {
+        Self {
+            world,
+            lights: HittableList::new(),
+            background: Color::zero(),
+            camera: CameraConfig::new(Point3::new(13.0, 2.0, 3.0), Point3::zero()),
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 400,
+            samples_per_pixel: 100,
+            files: Vec::new(),
+        }
+    }
+
+    /// Builds one of the scenes listed in [`SCENE_NAMES`]. Random content is
+    /// drawn from the calling thread's generator; call
+    /// [`seed_rng`](crate::rtweekend::seed_rng) first for a reproducible
+    /// layout.
+    pub fn by_name(name: &str) -> Option<Self> {
+        let scene = match name {
+            "random_scene" => Self {
+                background: Color::new(0.70, 0.80, 1.00),
+                camera: book_camera().aperture(0.1).focus_distance(10.0),
+                ..Self::new(random_scene())
+            },
+            "two_spheres" => Self {
+                background: Color::new(0.70, 0.80, 1.00),
+                camera: book_camera(),
+                ..Self::new(two_spheres())
+            },
+            "two_perlin_spheres" => Self {
+                background: Color::new(0.70, 0.80, 1.00),
+                camera: book_camera(),
+                ..Self::new(two_perlin_spheres())
+            },
+            "earth" => Self {
+                background: Color::new(0.70, 0.80, 1.00),
+                camera: book_camera(),
+                files: vec![PathBuf::from(EARTH_MAP)],
+                ..Self::new(earth())
+            },
+            "simple_light" => Self {
+                samples_per_pixel: 400,
+                camera: CameraConfig::new(Point3::new(26.0, 3.0, 6.0), Point3::new(0.0, 2.0, 0.0))
+                    .vfov(20.0),
+                lights: light_list(XyRect::new(3.0, 5.0, 1.0, 3.0, -2.0, no_material())),
+                ..Self::new(simple_light())
+            },
+            "cornell_box" => Self {
+                aspect_ratio: 1.0,
+                image_width: 600,
+                samples_per_pixel: 200,
+                camera: cornell_camera(),
+                lights: light_list(XzRect::new(
+                    213.0,
+                    343.0,
+                    227.0,
+                    332.0,
+                    554.0,
+                    no_material(),
+                )),
+                ..Self::new(cornell_box())
+            },
+            "cornell_smoke" => Self {
+                aspect_ratio: 1.0,
+                image_width: 600,
+                samples_per_pixel: 200,
+                camera: cornell_camera(),
+                lights: light_list(XzRect::new(
+                    113.0,
+                    443.0,
+                    127.0,
+                    432.0,
+                    554.0,
+                    no_material(),
+                )),
+                ..Self::new(cornell_smoke())
+            },
+            "final_scene" => Self {
+                aspect_ratio: 1.0,
+                image_width: 800,
+                samples_per_pixel: 4000, //10000
+                camera: CameraConfig::new(
+                    Point3::new(478.0, 278.0, -600.0),
+                    Point3::new(278.0, 278.0, 0.0),
+                ),
+                lights: light_list(XzRect::new(
+                    123.0,
+                    423.0,
+                    147.0,
+                    412.0,
+                    554.0,
+                    no_material(),
+                )),
+                files: vec![PathBuf::from(EARTH_MAP)],
+                ..Self::new(final_scene())
+            },
+            _ => return None,
+        };
+        Some(scene)
+    }
+
+    pub fn camera(&self, aspect_ratio: f64) -> Arc<dyn CameraModel> {
+        self.camera.build(aspect_ratio)
+    }
+}
+
+/// The view of the scenes from the first two books.
+fn book_camera() -> CameraConfig {
+    CameraConfig::new(Point3::new(13.0, 2.0, 3.0), Point3::zero()).vfov(20.0)
+}
+
+fn cornell_camera() -> CameraConfig {
+    CameraConfig::new(
+        Point3::new(278.0, 278.0, -800.0),
+        Point3::new(278.0, 278.0, 0.0),
+    )
+}
+
+// Light lists are only sampled, never shaded, so their material is unused.
+fn no_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new_color(&Color::zero()))
+}
+
+fn light_list(light: impl Hittable + 'static) -> HittableList {
+    let mut lights = HittableList::new();
+    lights.add(Arc::new(light));
+    lights
+}
+
+pub fn random_scene() -> HittableList {
+    let mut world = HittableList::new();
+    let checker = Arc::new(CheckerTexture::new_color(
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(checker)),
+    )));
+    for a in -11..10 {
+        for b in -11..10 {
+            let choose_mat = random_f64();
+            let center = Point3::new(
+                a as f64 + 0.9 * random_f64(),
+                0.2,
+                b as f64 + 0.9 * random_f64(),
+            );
+            if center.sub(Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let sphere_material: Arc<dyn Material>;
+                if choose_mat < 0.8 {
+                    let albedo = Color::random() * Color::random();
+                    sphere_material = Arc::new(Lambertian::new_color(&albedo));
+                    let center2 = center + Vec3::new(0.0, random_f64_range(0.0, 0.5), 0.0);
+                    world.add(Arc::new(MovingSphere::new(
+                        center,
+                        center2,
+                        0.0,
+                        1.0,
+                        0.2,
+                        sphere_material.clone(),
+                    )));
+                } else if choose_mat < 0.95 {
+                    let albedo = Color::random_range(0.5, 1.0);
+                    let fuzz = random_f64_range(0.0, 0.5);
+                    sphere_material = Arc::new(Metal::new(&albedo, &fuzz));
+                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material.clone())));
+                } else {
+                    sphere_material = Arc::new(Dielectric::new(1.5));
+                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material.clone())));
+                }
+            }
+        }
+    }
+    let material1 = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        material1,
+    )));
+
+    let material2 = Arc::new(Lambertian::new_color(&Color::new(0.4, 0.2, 0.1)));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(-4.0, 1.0, 0.0),
+        1.0,
+        material2,
+    )));
+
+    let material3 = Arc::new(Metal::new(&Color::new(0.7, 0.6, 0.5), &0.0));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(4.0, 1.0, 0.0),
+        1.0,
+        material3,
+    )));
+    world
+}
+
+pub fn two_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+    let checker = Arc::new(CheckerTexture::new_color(
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -10.0, 0.0),
+        10.0,
+        Arc::new(Lambertian::new(checker.clone())),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 10.0, 0.0),
+        10.0,
+        Arc::new(Lambertian::new(checker)),
+    )));
+    objects
+}
+
+pub fn two_perlin_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+    let pertext = Arc::new(NoiseTexture::new(4.0));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(pertext.clone())),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        Arc::new(Lambertian::new(pertext)),
+    )));
+    objects
+}
+
+pub fn earth() -> HittableList {
+    let mut objects = HittableList::new();
+    let earth_texture = Arc::new(ImageTexture::new(EARTH_MAP));
+    let earth_surface = Arc::new(Lambertian::new(earth_texture));
+    let globe = Arc::new(Sphere::new(Point3::zero(), 2.0, earth_surface));
+    objects.add(globe);
+    objects
+}
+
+pub fn simple_light() -> HittableList {
+    let mut objects = HittableList::new();
+    let pertext = Arc::new(NoiseTexture::new(4.0));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(pertext.clone())),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        Arc::new(Lambertian::new(pertext)),
+    )));
+    let difflight = Arc::new(DiffuseLight::new_color(Color::new(4.0, 4.0, 4.0)));
+    objects.add(Arc::new(XyRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight)));
+    objects
+}
+
+pub fn cornell_box() -> HittableList {
+    let mut objects = HittableList::new();
+    let red = Arc::new(Lambertian::new_color(&Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new_color(&Color::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new_color(Color::new(15.0, 15.0, 15.0)));
+    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    objects.add(Arc::new(XzRect::new(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )));
+    objects.add(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XyRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    let box1_0 = Arc::new(BoxObject::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    ));
+    let box1_1 = Arc::new(RotateY::new(box1_0, 15.0));
+    let box1_2 = Arc::new(Translate::new(box1_1, Vec3::new(265.0, 0.0, 295.0)));
+    objects.add(box1_2);
+
+    let box2_0 = Arc::new(BoxObject::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        white,
+    ));
+    let box2_1 = Arc::new(RotateY::new(box2_0, -18.0));
+    let box2_2 = Arc::new(Translate::new(box2_1, Vec3::new(130.0, 0.0, 65.0)));
+    objects.add(box2_2);
+    objects
+}
+
+pub fn cornell_smoke() -> HittableList {
+    let mut objects = HittableList::new();
+    let red = Arc::new(Lambertian::new_color(&Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new_color(&Color::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new_color(Color::new(7.0, 7.0, 7.0)));
+    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    objects.add(Arc::new(XzRect::new(
+        113.0, 443.0, 127.0, 432.0, 554.0, light,
+    )));
+    objects.add(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XyRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+
+    let box1_0 = Arc::new(BoxObject::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    ));
+    let box1_1 = Arc::new(RotateY::new(box1_0, 15.0));
+    let box1_2 = Arc::new(Translate::new(box1_1, Vec3::new(265.0, 0.0, 295.0)));
+
+    let box2_0 = Arc::new(BoxObject::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        white,
+    ));
+    let box2_1 = Arc::new(RotateY::new(box2_0, -18.0));
+    let box2_2 = Arc::new(Translate::new(box2_1, Vec3::new(130.0, 0.0, 65.0)));
+
+    objects.add(Arc::new(ConstantMedium::new_color(
+        box1_2,
+        0.01,
+        Color::zero(),
+    )));
+    objects.add(Arc::new(ConstantMedium::new_color(
+        box2_2,
+        0.01,
+        Color::new(1.0, 1.0, 1.0),
+    )));
+    objects
+}
+
+pub fn final_scene() -> HittableList {
+    let mut boxes1 = HittableList::new();
+    let ground = Arc::new(Lambertian::new_color(&Color::new(0.48, 0.83, 0.53)));
+
+    let boxes_per_side = 20;
+    for i in 0..boxes_per_side {
+        for j in 0..boxes_per_side {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f64 * w;
+            let z0 = -1000.0 + j as f64 * w;
+            let y0 = 0.0;
+            let x1 = x0 + w;
+            let y1 = random_f64_range(1.0, 101.0);
+            let z1 = z0 + w;
+
+            boxes1.add(Arc::new(BoxObject::new(
+                Point3::new(x0, y0, z0),
+                Point3::new(x1, y1, z1),
+                ground.clone(),
+            )));
+        }
+    }
+
+    let mut objects = HittableList::new();
+
+    objects.add(BvhNode::new_list(boxes1, 0.0, 1.0));
+
+    let light = Arc::new(DiffuseLight::new_color(Color::new(7.0, 7.0, 7.0)));
+    objects.add(Arc::new(XzRect::new(
+        123.0, 423.0, 147.0, 412.0, 554.0, light,
+    )));
+
+    let center1 = Point3::new(400.0, 400.0, 200.0);
+    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+    let moving_sphere_material = Arc::new(Lambertian::new_color(&Color::new(0.7, 0.3, 0.1)));
+    objects.add(Arc::new(MovingSphere::new(
+        center1,
+        center2,
+        0.0,
+        1.0,
+        50.0,
+        moving_sphere_material,
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(260.0, 150.0, 45.0),
+        50.0,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 150.0, 145.0),
+        50.0,
+        Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.9), &1.0)),
+    )));
+
+    let boundary = Arc::new(Sphere::new(
+        Point3::new(360.0, 150.0, 145.0),
+        70.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    objects.add(boundary.clone());
+    objects.add(Arc::new(ConstantMedium::new_color(
+        boundary,
+        0.2,
+        Color::new(0.2, 0.4, 0.9),
+    )));
+    let boundary = Arc::new(Sphere::new(
+        Point3::zero(),
+        5000.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    objects.add(Arc::new(ConstantMedium::new_color(
+        boundary,
+        0.0001,
+        Color::new(1.0, 1.0, 1.0),
+    )));
+
+    let emat = Arc::new(Lambertian::new(Arc::new(ImageTexture::new(EARTH_MAP))));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(400.0, 200.0, 400.0),
+        100.0,
+        emat,
+    )));
+    let pertext = Arc::new(NoiseTexture::new(0.1));
+    objects.add(Arc::new(Sphere::new(
+        Point3::new(220.0, 280.0, 300.0),
+        80.0,
+        Arc::new(Lambertian::new(pertext)),
+    )));
+
+    let mut boxes2 = HittableList::new();
+    let white = Arc::new(Lambertian::new_color(&Color::new(0.73, 0.73, 0.73)));
+    let ns = 1000;
+    for _j in 0..ns {
+        boxes2.add(Arc::new(Sphere::new(
+            Point3::random_range(0.0, 165.0),
+            10.0,
+            white.clone(),
+        )));
+    }
+
+    let cluster = Mat4::translate(Vec3::new(-100.0, 270.0, 395.0))
+        * Mat4::rotate(Vec3::new(0.0, 1.0, 0.0), 15.0);
+    objects.add(Arc::new(
+        Instance::new(BvhNode::new_list(boxes2, 0.0, 1.0), cluster, None).unwrap(),
+    ));
+    objects
+}
@@ -0,0 +1,1097 @@
+//! Loader for the line-oriented `.scene` text format.
+//!
+//! Every line is one statement: a keyword, optional positional words and
+//! `key=value` parameters. Vectors and colors are written as `x,y,z`, and
+//! anything after `#` is a comment.
+//!
+//! ```text
+//! image width=600 aspect=1 spp=200
+//! camera lookfrom=278,278,-800 lookat=278,278,0 vfov=40
+//! background color=0,0,0
+//! texture checker checker odd=0.2,0.3,0.1 even=0.9,0.9,0.9
+//! material white lambertian albedo=0.73,0.73,0.73
+//! material light diffuse_light emit=15,15,15
+//! object lamp xz_rect x0=213 x1=343 z0=227 z1=332 k=554 material=light
+//! add lamp
+//! light lamp
+//! object tall box min=0,0,0 max=165,330,165 material=white
+//! object tall_r rotate_y object=tall angle=15
+//! translate object=tall_r offset=265,0,295
+//! group spheres bvh
+//!   sphere center=0,0,0 radius=10 material=white
+//! end
+//! add spheres
+//! ```
+//!
+//! The camera focuses on `lookat` unless `focus=` gives a distance. Its field
+//! of view is `vfov=` or `hfov=` in degrees, or `focal_length=` in
+//! millimetres on a `sensor_width=` (36 by default), and its lens is
+//! `aperture=` wide in scene units or set by `fstop=`, with `unit_mm=`
+//! millimetres per scene unit (1000 by default). `roll=` turns it about the
+//! view direction and `vup=` sets the up direction. `projection=` picks
+//! one of the [`Projection`] names, `perspective` by default.
+//!
+//! Triangle meshes are loaded from Wavefront OBJ files with
+//! `mesh file=model.obj`; an optional `material=` replaces the MTL materials.
+//!
+//! A bare primitive is added to the world (or to the innermost open
+//! `group`), while `object <name> ...` only defines it so it can be wrapped
+//! by `translate`, `rotate_y`, `transform` or `constant_medium`, or placed
+//! with `add`. `transform object=<name>` applies, in this order, an optional
+//! `scale=x,y,z`, `shear=xy,xz,yx,yz,zx,zy`, rotation by `angle=` degrees
+//! about `axis=x,y,z`, rotation by `quaternion=w,x,y,z` and `offset=x,y,z`.
+//!
+//! `instance object=<name>` takes the same parameters and an optional
+//! `material=` that replaces the object's materials. Instances share the
+//! object instead of copying it, so a `group <name> bvh` placed many times
+//! is stored and built once.
+//!
+//! `light <name>` additionally samples an object directly as a light source.
+//! It is taken as defined, so it must not be inside a transformed group. Only
+//! rectangles, boxes, spheres and their translations, rotations and
+//! transforms that keep angles, i.e. without uneven scale or shear, can be
+//! sampled; meshes cannot.
+
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::box_object::BoxObject;
+use crate::bvh::BvhNode;
+use crate::camera::{Aperture, FieldOfView, Focus, Projection};
+use crate::constant_medium::ConstantMedium;
+use crate::hittable::{Hittable, RotateY, Transform, Translate};
+use crate::hittable_list::HittableList;
+use crate::instance::Instance;
+use crate::mat4::Mat4;
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::moving_sphere::MovingSphere;
+use crate::obj_loader::load_obj_with_files;
+use crate::rtweekend::parse_aspect_ratio;
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use crate::vec3::{Color, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Mul;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneError {
+    /// 1-based line number, or 0 for errors not tied to a line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl SceneError {
+    fn new(line: usize, message: String) -> Self {
+        Self { line, message }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+pub fn load(path: &Path) -> Result<Scene, SceneError> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| SceneError::new(0, format!("cannot read \"{}\": {}", path.display(), e)))?;
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    parse(&source, &base_dir)
+}
+
+/// Parses scene source text. Relative texture paths are resolved against
+/// `base_dir`.
+pub fn parse(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let mut loader = Loader {
+        base_dir: base_dir.to_path_buf(),
+        scene: Scene::new(HittableList::new()),
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        objects: HashMap::new(),
+        groups: Vec::new(),
+    };
+    for (index, text) in source.lines().enumerate() {
+        let text = text.split('#').next().unwrap_or_default();
+        if let Some(mut stmt) = Statement::parse(index + 1, text)? {
+            loader.statement(&mut stmt)?;
+            stmt.finish()?;
+        }
+    }
+    if let Some(group) = loader.groups.last() {
+        return Err(SceneError::new(
+            group.line,
+            format!("group '{}' is never closed with 'end'", group.name),
+        ));
+    }
+    if loader.scene.world.objects.is_empty() {
+        return Err(SceneError::new(
+            source.lines().count(),
+            String::from("the scene has no objects"),
+        ));
+    }
+    Ok(loader.scene)
+}
+
+struct Statement<'a> {
+    line: usize,
+    keyword: &'a str,
+    words: Vec<&'a str>,
+    params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Statement<'a> {
+    fn parse(line: usize, text: &'a str) -> Result<Option<Self>, SceneError> {
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => return Ok(None),
+        };
+        let mut stmt = Self {
+            line,
+            keyword,
+            words: Vec::new(),
+            params: Vec::new(),
+        };
+        for token in tokens {
+            if let Some((key, value)) = token.split_once('=') {
+                if key.is_empty() || value.is_empty() {
+                    return Err(stmt.error(format!("malformed parameter '{}'", token)));
+                }
+                if stmt.params.iter().any(|(k, _)| *k == key) {
+                    return Err(stmt.error(format!("parameter '{}' given twice", key)));
+                }
+                stmt.params.push((key, value));
+            } else if stmt.params.is_empty() {
+                stmt.words.push(token);
+            } else {
+                return Err(stmt.error(format!(
+                    "unexpected '{}' after parameters, expected key=value",
+                    token
+                )));
+            }
+        }
+        Ok(Some(stmt))
+    }
+
+    fn error(&self, message: String) -> SceneError {
+        SceneError::new(self.line, message)
+    }
+
+    /// Removes and returns the next positional word.
+    fn word(&mut self, what: &str) -> Result<&'a str, SceneError> {
+        if self.words.is_empty() {
+            Err(self.error(format!("'{}' expects a {}", self.keyword, what)))
+        } else {
+            Ok(self.words.remove(0))
+        }
+    }
+
+    fn take(&mut self, key: &str) -> Option<&'a str> {
+        let index = self.params.iter().position(|(k, _)| *k == key)?;
+        Some(self.params.remove(index).1)
+    }
+
+    fn required(&mut self, key: &str) -> Result<&'a str, SceneError> {
+        self.take(key).ok_or_else(|| {
+            self.error(format!(
+                "'{}' is missing required parameter '{}'",
+                self.keyword, key
+            ))
+        })
+    }
+
+    fn number(&mut self, key: &str) -> Result<f64, SceneError> {
+        let value = self.required(key)?;
+        self.parse_number(key, value)
+    }
+
+    fn number_or(&mut self, key: &str, default: f64) -> Result<f64, SceneError> {
+        match self.take(key) {
+            Some(value) => self.parse_number(key, value),
+            None => Ok(default),
+        }
+    }
+
+    fn parse_number(&self, key: &str, value: &str) -> Result<f64, SceneError> {
+        match value.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(n),
+            _ => Err(self.error(format!("'{}' expects a number, got '{}'", key, value))),
+        }
+    }
+
+    fn integer(&mut self, key: &str) -> Result<Option<usize>, SceneError> {
+        match self.take(key) {
+            Some(value) => match value.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err(self.error(format!(
+                    "'{}' expects a positive integer, got '{}'",
+                    key, value
+                ))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// `count` comma-separated numbers, if the parameter is given.
+    fn numbers(&mut self, key: &str, count: usize) -> Result<Option<Vec<f64>>, SceneError> {
+        let value = match self.take(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let numbers: Vec<f64> = value
+            .split(',')
+            .filter_map(|p| p.parse::<f64>().ok().filter(|n| n.is_finite()))
+            .collect();
+        if numbers.len() != count || value.split(',').count() != count {
+            return Err(self.error(format!(
+                "'{}' expects {} comma-separated numbers, got '{}'",
+                key, count, value
+            )));
+        }
+        Ok(Some(numbers))
+    }
+
+    fn vector(&mut self, key: &str) -> Result<Vec3, SceneError> {
+        let value = self.required(key)?;
+        parse_vector(value)
+            .ok_or_else(|| self.error(format!("'{}' expects x,y,z, got '{}'", key, value)))
+    }
+
+    fn vector_or(&mut self, key: &str, default: Vec3) -> Result<Vec3, SceneError> {
+        if self.params.iter().any(|(k, _)| *k == key) {
+            self.vector(key)
+        } else {
+            Ok(default)
+        }
+    }
+
+    /// Errors on any parameter or word the statement handler did not consume.
+    fn finish(&self) -> Result<(), SceneError> {
+        if let Some(word) = self.words.first() {
+            return Err(self.error(format!("unexpected '{}'", word)));
+        }
+        if let Some((key, _)) = self.params.first() {
+            return Err(self.error(format!(
+                "unknown parameter '{}' for '{}'",
+                key, self.keyword
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn parse_vector(value: &str) -> Option<Vec3> {
+    let mut parts = value.split(',').map(|p| p.parse::<f64>().ok());
+    let v = Vec3::new(parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || !(v.x.is_finite() && v.y.is_finite() && v.z.is_finite()) {
+        return None;
+    }
+    Some(v)
+}
+
+struct Group {
+    name: String,
+    line: usize,
+    bvh: bool,
+    list: HittableList,
+}
+
+struct Loader {
+    base_dir: PathBuf,
+    scene: Scene,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    objects: HashMap<String, Arc<dyn Hittable>>,
+    groups: Vec<Group>,
+}
+
+impl Loader {
+    fn statement(&mut self, stmt: &mut Statement) -> Result<(), SceneError> {
+        match stmt.keyword {
+            "image" => self.image(stmt),
+            "camera" => self.camera(stmt),
+            "background" => {
+                self.scene.background = stmt.vector("color")?;
+                Ok(())
+            }
+            "texture" => {
+                let name = self.new_name(stmt, "texture", |l, n| l.textures.contains_key(n))?;
+                let texture = self.texture(stmt)?;
+                self.textures.insert(name, texture);
+                Ok(())
+            }
+            "material" => {
+                let name = self.new_name(stmt, "material", |l, n| l.materials.contains_key(n))?;
+                let material = self.material(stmt)?;
+                self.materials.insert(name, material);
+                Ok(())
+            }
+            "object" => {
+                let name = self.new_name(stmt, "object", |l, n| l.objects.contains_key(n))?;
+                stmt.keyword = stmt.word("primitive kind")?;
+                let object = self.primitive(stmt)?;
+                self.objects.insert(name, object);
+                Ok(())
+            }
+            "add" => {
+                let name = stmt.word("object name")?;
+                let object = self.object(stmt, name)?;
+                self.add(object);
+                Ok(())
+            }
+            "light" => {
+                let name = stmt.word("object name")?;
+                let object = self.object(stmt, name)?;
+                if !can_sample(&*object) {
+                    return Err(stmt.error(format!("'{}' cannot be sampled as a light", name)));
+                }
+                self.scene.lights.add(object);
+                Ok(())
+            }
+            "group" => {
+                let name = self.new_name(stmt, "object", |l, n| {
+                    l.objects.contains_key(n) || l.groups.iter().any(|g| g.name == n)
+                })?;
+                let bvh = match stmt.words.first() {
+                    Some(&"bvh") => {
+                        stmt.words.remove(0);
+                        true
+                    }
+                    _ => false,
+                };
+                self.groups.push(Group {
+                    name,
+                    line: stmt.line,
+                    bvh,
+                    list: HittableList::new(),
+                });
+                Ok(())
+            }
+            "end" => {
+                let group = self
+                    .groups
+                    .pop()
+                    .ok_or_else(|| stmt.error(String::from("'end' without an open group")))?;
+                if group.list.objects.is_empty() {
+                    return Err(stmt.error(format!("group '{}' is empty", group.name)));
+                }
+                let object: Arc<dyn Hittable> = if group.bvh {
+                    BvhNode::new_list(group.list, 0.0, 1.0)
+                } else {
+                    Arc::new(group.list)
+                };
+                self.objects.insert(group.name, object);
+                Ok(())
+            }
+            _ => {
+                let object = self.primitive(stmt)?;
+                self.add(object);
+                Ok(())
+            }
+        }
+    }
+
+    fn new_name(
+        &self,
+        stmt: &mut Statement,
+        what: &str,
+        exists: impl Fn(&Self, &str) -> bool,
+    ) -> Result<String, SceneError> {
+        let name = stmt.word(&format!("{} name", what))?;
+        if exists(self, name) {
+            return Err(stmt.error(format!("{} '{}' is already defined", what, name)));
+        }
+        Ok(name.to_string())
+    }
+
+    fn add(&mut self, object: Arc<dyn Hittable>) {
+        match self.groups.last_mut() {
+            Some(group) => group.list.add(object),
+            None => self.scene.world.add(object),
+        }
+    }
+
+    fn image(&mut self, stmt: &mut Statement) -> Result<(), SceneError> {
+        if let Some(width) = stmt.integer("width")? {
+            self.scene.image_width = width;
+        }
+        if let Some(spp) = stmt.integer("spp")? {
+            self.scene.samples_per_pixel = spp;
+        }
+        if let Some(value) = stmt.take("aspect") {
+            let ratio = parse_aspect_ratio(value).ok_or_else(|| {
+                stmt.error(format!(
+                    "'aspect' expects a positive number or W:H, got '{}'",
+                    value
+                ))
+            })?;
+            self.scene.aspect_ratio = ratio;
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self, stmt: &mut Statement) -> Result<(), SceneError> {
+        let camera = &mut self.scene.camera;
+        camera.lookfrom = stmt.vector_or("lookfrom", camera.lookfrom)?;
+        camera.lookat = stmt.vector_or("lookat", camera.lookat)?;
+        camera.vup = stmt.vector_or("vup", camera.vup)?;
+        camera.roll = stmt.number_or("roll", camera.roll)?;
+        if let Some(value) = stmt.take("projection") {
+            camera.projection = Projection::from_name(value).ok_or_else(|| {
+                stmt.error(format!(
+                    "unknown projection '{}', expected one of: {}",
+                    value,
+                    Projection::NAMES.join(", ")
+                ))
+            })?;
+        }
+        let fovs = [
+            ("vfov", FieldOfView::Vertical as fn(f64) -> FieldOfView),
+            ("hfov", FieldOfView::Horizontal),
+            ("focal_length", FieldOfView::FocalLength),
+        ];
+        for (key, fov) in fovs {
+            if let Some(value) = stmt.take(key) {
+                camera.fov = fov(stmt.parse_number(key, value)?);
+            }
+        }
+        camera.sensor_width = stmt.number_or("sensor_width", camera.sensor_width)?;
+        if let Some(value) = stmt.take("aperture") {
+            camera.aperture = Aperture::Diameter(stmt.parse_number("aperture", value)?);
+        }
+        if let Some(value) = stmt.take("fstop") {
+            camera.aperture = Aperture::FStop(stmt.parse_number("fstop", value)?);
+        }
+        camera.focus = match stmt.take("focus") {
+            Some("lookat") => Focus::LookAt,
+            Some(value) => Focus::Distance(stmt.parse_number("focus", value)?),
+            None => camera.focus,
+        };
+        camera.unit_mm = stmt.number_or("unit_mm", camera.unit_mm)?;
+        Ok(())
+    }
+
+    fn texture(&mut self, stmt: &mut Statement) -> Result<Arc<dyn Texture>, SceneError> {
+        let kind = stmt.word("texture kind")?;
+        let texture: Arc<dyn Texture> = match kind {
+            "solid" => Arc::new(SolidColor::new(stmt.vector("color")?)),
+            "checker" => {
+                let odd = self.texture_param(stmt, "odd")?;
+                let even = self.texture_param(stmt, "even")?;
+                Arc::new(CheckerTexture::new(odd, even))
+            }
+            "noise" => Arc::new(NoiseTexture::new(stmt.number("scale")?)),
+            "image" => {
+                let file = self.base_dir.join(stmt.required("file")?);
+                let texture = ImageTexture::open(&file.to_string_lossy()).map_err(|e| {
+                    stmt.error(format!("cannot load image \"{}\": {}", file.display(), e))
+                })?;
+                self.scene.files.push(file);
+                Arc::new(texture)
+            }
+            _ => return Err(stmt.error(format!("unknown texture kind '{}'", kind))),
+        };
+        Ok(texture)
+    }
+
+    /// A texture parameter is either an inline `r,g,b` color or the name of a
+    /// texture defined earlier.
+    fn texture_param(
+        &self,
+        stmt: &mut Statement,
+        key: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        let value = stmt.required(key)?;
+        if let Some(color) = parse_vector(value) {
+            return Ok(Arc::new(SolidColor::new(color)));
+        }
+        self.textures
+            .get(value)
+            .cloned()
+            .ok_or_else(|| stmt.error(format!("unknown texture '{}'", value)))
+    }
+
+    fn material(&self, stmt: &mut Statement) -> Result<Arc<dyn Material>, SceneError> {
+        let kind = stmt.word("material kind")?;
+        let material: Arc<dyn Material> = match kind {
+            "lambertian" => Arc::new(Lambertian::new(self.texture_param(stmt, "albedo")?)),
+            "metal" => {
+                let albedo: Color = stmt.vector("albedo")?;
+                let fuzz = stmt.number_or("fuzz", 0.0)?;
+                Arc::new(Metal::new(&albedo, &fuzz))
+            }
+            "dielectric" => Arc::new(Dielectric::new(stmt.number("ir")?)),
+            "diffuse_light" => Arc::new(DiffuseLight::new(self.texture_param(stmt, "emit")?)),
+            "isotropic" => Arc::new(Isotropic::new_texture(self.texture_param(stmt, "albedo")?)),
+            _ => return Err(stmt.error(format!("unknown material kind '{}'", kind))),
+        };
+        Ok(material)
+    }
+
+    fn material_param(&self, stmt: &mut Statement) -> Result<Arc<dyn Material>, SceneError> {
+        let name = stmt.required("material")?;
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| stmt.error(format!("unknown material '{}'", name)))
+    }
+
+    fn optional_material_param(
+        &self,
+        stmt: &mut Statement,
+    ) -> Result<Option<Arc<dyn Material>>, SceneError> {
+        match stmt.take("material") {
+            Some(name) => match self.materials.get(name) {
+                Some(material) => Ok(Some(material.clone())),
+                None => Err(stmt.error(format!("unknown material '{}'", name))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn object(&self, stmt: &Statement, name: &str) -> Result<Arc<dyn Hittable>, SceneError> {
+        if let Some(object) = self.objects.get(name) {
+            return Ok(object.clone());
+        }
+        if self.groups.iter().any(|g| g.name == name) {
+            return Err(stmt.error(format!("group '{}' is still open", name)));
+        }
+        Err(stmt.error(format!("unknown object '{}'", name)))
+    }
+
+    fn object_param(
+        &self,
+        stmt: &mut Statement,
+        key: &str,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let name = stmt.required(key)?;
+        self.object(stmt, name)
+    }
+
+    fn primitive(&mut self, stmt: &mut Statement) -> Result<Arc<dyn Hittable>, SceneError> {
+        let object: Arc<dyn Hittable> = match stmt.keyword {
+            "sphere" => Arc::new(Sphere::new(
+                stmt.vector("center")?,
+                stmt.number("radius")?,
+                self.material_param(stmt)?,
+            )),
+            "moving_sphere" => Arc::new(MovingSphere::new(
+                stmt.vector("center0")?,
+                stmt.vector("center1")?,
+                stmt.number_or("time0", 0.0)?,
+                stmt.number_or("time1", 1.0)?,
+                stmt.number("radius")?,
+                self.material_param(stmt)?,
+            )),
+            "xy_rect" => Arc::new(XyRect::new(
+                stmt.number("x0")?,
+                stmt.number("x1")?,
+                stmt.number("y0")?,
+                stmt.number("y1")?,
+                stmt.number("k")?,
+                self.material_param(stmt)?,
+            )),
+            "xz_rect" => Arc::new(XzRect::new(
+                stmt.number("x0")?,
+                stmt.number("x1")?,
+                stmt.number("z0")?,
+                stmt.number("z1")?,
+                stmt.number("k")?,
+                self.material_param(stmt)?,
+            )),
+            "yz_rect" => Arc::new(YzRect::new(
+                stmt.number("y0")?,
+                stmt.number("y1")?,
+                stmt.number("z0")?,
+                stmt.number("z1")?,
+                stmt.number("k")?,
+                self.material_param(stmt)?,
+            )),
+            "box" => Arc::new(BoxObject::new(
+                stmt.vector("min")?,
+                stmt.vector("max")?,
+                self.material_param(stmt)?,
+            )),
+            "mesh" => {
+                let file = self.base_dir.join(stmt.required("file")?);
+                let material = self.optional_material_param(stmt)?;
+                let (mesh, files) =
+                    load_obj_with_files(&file, material).map_err(|e| stmt.error(e.to_string()))?;
+                self.scene.files.extend(files);
+                Arc::new(mesh)
+            }
+            "constant_medium" => {
+                let boundary = self.object_param(stmt, "boundary")?;
+                let density = stmt.number("density")?;
+                if density <= 0.0 {
+                    return Err(stmt.error(String::from("'density' must be positive")));
+                }
+                let albedo = self.texture_param(stmt, "albedo")?;
+                Arc::new(ConstantMedium::new_texture(boundary, density, albedo))
+            }
+            "translate" => Arc::new(Translate::new(
+                self.object_param(stmt, "object")?,
+                stmt.vector("offset")?,
+            )),
+            "rotate_y" => Arc::new(RotateY::new(
+                self.object_param(stmt, "object")?,
+                stmt.number("angle")?,
+            )),
+            "transform" => {
+                let object = self.object_param(stmt, "object")?;
+                let matrix = transform_param(stmt)?;
+                Arc::new(
+                    Transform::new(object, matrix)
+                        .ok_or_else(|| stmt.error(String::from("transform cannot be inverted")))?,
+                )
+            }
+            "instance" => {
+                let geometry = self.object_param(stmt, "object")?;
+                let material = self.optional_material_param(stmt)?;
+                let matrix = transform_param(stmt)?;
+                Arc::new(
+                    Instance::new(geometry, matrix, material)
+                        .ok_or_else(|| stmt.error(String::from("transform cannot be inverted")))?,
+                )
+            }
+            kind => return Err(stmt.error(format!("unknown statement '{}'", kind))),
+        };
+        Ok(object)
+    }
+}
+
+/// The matrix of a `transform` statement: scale, then shear, rotate and
+/// translate, each optional.
+fn transform_param(stmt: &mut Statement) -> Result<Mat4, SceneError> {
+    let mut matrix = Mat4::identity();
+    if stmt.params.iter().any(|(k, _)| *k == "scale") {
+        matrix = Mat4::scale(stmt.vector("scale")?);
+    }
+    if let Some(s) = stmt.numbers("shear", 6)? {
+        matrix = Mat4::shear(s[0], s[1], s[2], s[3], s[4], s[5]) * matrix;
+    }
+    if stmt.params.iter().any(|(k, _)| *k == "axis") {
+        let axis = stmt.vector("axis")?;
+        if axis.near_zero() {
+            return Err(stmt.error(String::from("'axis' must not be zero")));
+        }
+        matrix = Mat4::rotate(axis, stmt.number("angle")?) * matrix;
+    }
+    if let Some(q) = stmt.numbers("quaternion", 4)? {
+        if q.iter().all(|&v| v == 0.0) {
+            return Err(stmt.error(String::from("'quaternion' must not be zero")));
+        }
+        matrix = Mat4::from_quaternion(q[0], q[1], q[2], q[3]) * matrix;
+    }
+    let offset = stmt.vector_or("offset", Vec3::zero())?;
+    Ok(Mat4::translate(offset) * matrix)
+}
+
+/// Whether `object` implements light sampling, probed from a point outside
+/// its bounding box.
+fn can_sample(object: &dyn Hittable) -> bool {
+    let bbox = match object.bounding_box(0.0, 1.0) {
+        Some(bbox) => bbox,
+        None => return false,
+    };
+    let extent = (bbox.maximum - bbox.minimum).length() + 1.0;
+    let origin = bbox.maximum + Vec3::new(1.0, 2.0, 3.0).mul(extent);
+    let direction = object.random(&origin, (0.5, 0.5));
+    object.pdf_value(&origin, &direction) > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::vec3::Point3;
+
+    fn scenes_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenes")
+    }
+
+    /// A material and a sphere, for scenes that would otherwise be empty.
+    const BALL: &str = "material ball lambertian albedo=1,1,1
+                        sphere center=0,0,0 radius=1 material=ball";
+
+    fn parse_ok(source: &str) -> Scene {
+        match parse(source, &scenes_dir()) {
+            Ok(scene) => scene,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn parse_err(source: &str) -> SceneError {
+        match parse(source, &scenes_dir()) {
+            Ok(_) => panic!("parsed: {}", source),
+            Err(e) => e,
+        }
+    }
+
+    /// Distance along a ray from `origin` towards `direction` to the first
+    /// object of `scene`.
+    fn hit_t(scene: &Scene, origin: Point3, direction: Vec3) -> Option<f64> {
+        let ray = Ray::new(&origin, &direction, 0.0);
+        scene.world.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t)
+    }
+
+    fn assert_close(a: Option<f64>, b: f64) {
+        assert!(a.is_some_and(|a| (a - b).abs() < 1e-6), "{:?} != {}", a, b);
+    }
+
+    #[test]
+    fn documented_example_parses() {
+        let scene = parse_ok(
+            "image width=600 aspect=1 spp=200
+             camera lookfrom=278,278,-800 lookat=278,278,0 vfov=40
+             background color=0,0,0
+             texture checker checker odd=0.2,0.3,0.1 even=0.9,0.9,0.9
+             material white lambertian albedo=0.73,0.73,0.73
+             material light diffuse_light emit=15,15,15
+             object lamp xz_rect x0=213 x1=343 z0=227 z1=332 k=554 material=light
+             add lamp
+             light lamp
+             object tall box min=0,0,0 max=165,330,165 material=white
+             object tall_r rotate_y object=tall angle=15
+             translate object=tall_r offset=265,0,295
+             group spheres bvh
+               sphere center=0,0,0 radius=10 material=white
+             end
+             add spheres",
+        );
+        assert_eq!(scene.image_width, 600);
+        assert_eq!(scene.aspect_ratio, 1.0);
+        assert_eq!(scene.samples_per_pixel, 200);
+        assert_eq!(scene.camera.lookfrom, Point3::new(278.0, 278.0, -800.0));
+        assert_eq!(scene.camera.fov, FieldOfView::Vertical(40.0));
+        assert_eq!(scene.world.objects.len(), 3);
+        assert_eq!(scene.lights.objects.len(), 1);
+    }
+
+    #[test]
+    fn repository_scenes_parse() {
+        let mut count = 0;
+        for entry in std::fs::read_dir(scenes_dir()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "scene") {
+                if let Err(e) = load(&path) {
+                    panic!("{}: {}", path.display(), e);
+                }
+                count += 1;
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn camera_statement() {
+        let scene = parse_ok(&format!(
+            "camera lookfrom=1,2,3 lookat=0,1,0 vup=0,0,1 roll=10 projection=fisheye_equisolid \
+             focal_length=50 sensor_width=24 fstop=2.8 focus=7 unit_mm=10\n{}",
+            BALL
+        ));
+        let camera = scene.camera;
+        assert_eq!(camera.lookfrom, Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(camera.lookat, Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(camera.vup, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(camera.roll, 10.0);
+        assert_eq!(camera.projection, Projection::FisheyeEquisolid);
+        assert_eq!(camera.fov, FieldOfView::FocalLength(50.0));
+        assert_eq!(camera.sensor_width, 24.0);
+        assert_eq!(camera.aperture, Aperture::FStop(2.8));
+        assert_eq!(camera.focus, Focus::Distance(7.0));
+        assert_eq!(camera.unit_mm, 10.0);
+
+        let camera = parse_ok(&format!(
+            "camera hfov=70 aperture=0.5 focus=lookat\n{}",
+            BALL
+        ))
+        .camera;
+        assert_eq!(camera.fov, FieldOfView::Horizontal(70.0));
+        assert_eq!(camera.aperture, Aperture::Diameter(0.5));
+        assert_eq!(camera.focus, Focus::LookAt);
+    }
+
+    #[test]
+    fn image_and_background_statements() {
+        let scene = parse_ok(&format!(
+            "image width=320 aspect=4:3 spp=16\nbackground color=0.1,0.2,0.3\n{}",
+            BALL
+        ));
+        assert_eq!(scene.image_width, 320);
+        assert_eq!(scene.aspect_ratio, 4.0 / 3.0);
+        assert_eq!(scene.samples_per_pixel, 16);
+        assert_eq!(scene.background, Color::new(0.1, 0.2, 0.3));
+    }
+
+    #[test]
+    fn texture_and_material_statements() {
+        let scene = parse_ok(
+            "texture red solid color=1,0,0
+             texture check checker odd=red even=0,0,1
+             texture marble noise scale=4
+             texture earth image file=../earthmap.jpg
+             material a lambertian albedo=check
+             material b metal albedo=0.8,0.8,0.8 fuzz=0.1
+             material c dielectric ir=1.5
+             material d diffuse_light emit=marble
+             material e isotropic albedo=earth
+             sphere center=0,0,0 radius=1 material=a
+             sphere center=0,0,3 radius=1 material=b
+             sphere center=0,0,6 radius=1 material=c
+             sphere center=0,0,9 radius=1 material=d
+             sphere center=0,0,12 radius=1 material=e",
+        );
+        assert_eq!(scene.world.objects.len(), 5);
+        assert_eq!(scene.files, [scenes_dir().join("../earthmap.jpg")]);
+    }
+
+    #[test]
+    fn primitive_statements() {
+        let scene = parse_ok(
+            "material m lambertian albedo=0.5,0.5,0.5
+             sphere center=0,0,-10 radius=1 material=m
+             moving_sphere center0=5,0,-10 center1=5,1,-10 time0=0 time1=1 radius=1 material=m
+             xy_rect x0=-1 x1=1 y0=9 y1=11 k=-10 material=m
+             xz_rect x0=9 x1=11 z0=-11 z1=-9 k=-1 material=m
+             yz_rect y0=19 y1=21 z0=-11 z1=-9 k=1 material=m
+             box min=-1,-21,-11 max=1,-19,-9 material=m
+             mesh file=models/icosphere.obj material=m",
+        );
+        assert_eq!(scene.world.objects.len(), 7);
+        assert_eq!(scene.files, [scenes_dir().join("models/icosphere.obj")]);
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        assert_close(hit_t(&scene, Point3::new(0.0, 0.0, 0.0), down), 9.0);
+        assert_close(hit_t(&scene, Point3::new(5.0, 0.0, 0.0), down), 9.0);
+        assert_close(hit_t(&scene, Point3::new(0.0, 10.0, 0.0), down), 10.0);
+        assert_close(hit_t(&scene, Point3::new(0.0, -20.0, 0.0), down), 9.0);
+        let along_y = Vec3::new(0.0, -1.0, 0.0);
+        assert_close(hit_t(&scene, Point3::new(10.0, 0.0, -10.0), along_y), 1.0);
+        let along_x = Vec3::new(-1.0, 0.0, 0.0);
+        assert_close(hit_t(&scene, Point3::new(2.0, 20.0, -10.0), along_x), 1.0);
+    }
+
+    #[test]
+    fn wrapper_statements() {
+        let scene = parse_ok(
+            "material m lambertian albedo=0.5,0.5,0.5
+             material other lambertian albedo=0.9,0.1,0.1
+             object ball sphere center=0,0,0 radius=1 material=m
+             object cube box min=-1,-1,-1 max=1,1,1 material=m
+             translate object=ball offset=0,0,-10
+             object spun rotate_y object=cube angle=45
+             translate object=spun offset=10,0,-10
+             transform object=ball scale=2,2,2 offset=20,0,-10
+             instance object=ball material=other axis=0,1,0 angle=90 offset=30,0,-10
+             object far translate object=ball offset=40,0,-10
+             constant_medium boundary=far density=1000000 albedo=1,1,1",
+        );
+        assert_eq!(scene.world.objects.len(), 5);
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        assert_close(hit_t(&scene, Point3::new(0.0, 0.0, 0.0), down), 9.0);
+        assert_close(
+            hit_t(&scene, Point3::new(10.0, 0.0, 0.0), down),
+            10.0 - 2f64.sqrt(),
+        );
+        assert_close(hit_t(&scene, Point3::new(20.0, 0.0, 0.0), down), 8.0);
+        assert_close(hit_t(&scene, Point3::new(30.0, 0.0, 0.0), down), 9.0);
+        // The dense medium scatters right behind its boundary.
+        let t = hit_t(&scene, Point3::new(40.0, 0.0, 0.0), down).unwrap();
+        assert!((9.0..9.01).contains(&t), "{}", t);
+    }
+
+    #[test]
+    fn group_add_and_light_statements() {
+        let scene = parse_ok(
+            "material m diffuse_light emit=1,1,1
+             object lamp sphere center=0,0,-10 radius=1 material=m
+             group pair bvh
+               add lamp
+               group inner
+                 sphere center=5,0,-10 radius=1 material=m
+               end
+               add inner
+             end
+             add pair
+             light lamp",
+        );
+        assert_eq!(scene.world.objects.len(), 1);
+        assert_eq!(scene.lights.objects.len(), 1);
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        assert_close(hit_t(&scene, Point3::new(5.0, 0.0, 0.0), down), 9.0);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let scene = parse_ok(&format!(
+            "# a comment\n\n   \nimage width=10 # trailing\n{}",
+            BALL
+        ));
+        assert_eq!(scene.image_width, 10);
+    }
+
+    #[test]
+    fn unknown_keywords_are_rejected() {
+        let e = parse_err("image width=10\n\nteapot size=1");
+        assert_eq!(
+            e,
+            SceneError::new(3, String::from("unknown statement 'teapot'"))
+        );
+        let e = parse_err("material m lambertian albedo=1,1,1\nsphere center=0,0,0 radius=1 material=m colour=red");
+        assert_eq!(e.line, 2);
+        assert_eq!(e.message, "unknown parameter 'colour' for 'sphere'");
+        let e = parse_err("texture t wood");
+        assert_eq!(e.message, "unknown texture kind 'wood'");
+        let e = parse_err("\nmaterial m plastic");
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (2, "unknown material kind 'plastic'")
+        );
+        let e = parse_err("camera projection=fisheye");
+        assert!(e.message.starts_with("unknown projection 'fisheye'"));
+    }
+
+    #[test]
+    fn wrong_arity_is_rejected() {
+        let e = parse_err("background color=1,2");
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (1, "'color' expects x,y,z, got '1,2'")
+        );
+        let e = parse_err("background color=1,2,3,4");
+        assert_eq!(e.message, "'color' expects x,y,z, got '1,2,3,4'");
+        let e = parse_err(
+            "material m lambertian albedo=1,1,1
+             object ball sphere center=0,0,0 radius=1 material=m
+             transform object=ball shear=1,0,0,0,0",
+        );
+        assert_eq!(e.line, 3);
+        assert_eq!(
+            e.message,
+            "'shear' expects 6 comma-separated numbers, got '1,0,0,0,0'"
+        );
+        let e = parse_err("\nmaterial");
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (2, "'material' expects a material name")
+        );
+        let e = parse_err("sphere center=0,0,0");
+        assert_eq!(e.message, "'sphere' is missing required parameter 'radius'");
+        let e = parse_err("background color=0,0,0 extra");
+        assert_eq!(
+            e.message,
+            "unexpected 'extra' after parameters, expected key=value"
+        );
+        let e = parse_err("background color=0,0,0 color=1,1,1");
+        assert_eq!(e.message, "parameter 'color' given twice");
+    }
+
+    #[test]
+    fn bad_numbers_are_rejected() {
+        let e = parse_err(
+            "material m lambertian albedo=1,1,1\n\nsphere center=0,0,0 radius=big material=m",
+        );
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (3, "'radius' expects a number, got 'big'")
+        );
+        let e = parse_err(
+            "material m lambertian albedo=1,1,1\nsphere center=0,0,0 radius=inf material=m",
+        );
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (2, "'radius' expects a number, got 'inf'")
+        );
+        let e = parse_err("image width=0");
+        assert_eq!(e.message, "'width' expects a positive integer, got '0'");
+        let e = parse_err("image aspect=4:0");
+        assert_eq!(
+            e.message,
+            "'aspect' expects a positive number or W:H, got '4:0'"
+        );
+        let e = parse_err("background color=1,x,1");
+        assert_eq!(e.message, "'color' expects x,y,z, got '1,x,1'");
+    }
+
+    #[test]
+    fn groups_must_be_closed_and_nonempty() {
+        let e = parse_err(
+            "material m lambertian albedo=1,1,1
+             group spheres bvh
+               sphere center=0,0,0 radius=1 material=m
+             ",
+        );
+        assert_eq!(
+            e,
+            SceneError::new(
+                2,
+                String::from("group 'spheres' is never closed with 'end'")
+            )
+        );
+        let e = parse_err("group outer\ngroup inner bvh\nend\n");
+        assert_eq!((e.line, e.message.as_str()), (3, "group 'inner' is empty"));
+        let e = parse_err(
+            "material m lambertian albedo=1,1,1
+             sphere center=0,0,0 radius=1 material=m
+             group g
+             end
+             add g",
+        );
+        assert_eq!((e.line, e.message.as_str()), (4, "group 'g' is empty"));
+        let e = parse_err(&format!("group g\n{}\nend\nend", BALL));
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (5, "'end' without an open group")
+        );
+        let e = parse_err("group g\nadd g");
+        assert_eq!((e.line, e.message.as_str()), (2, "group 'g' is still open"));
+    }
+
+    #[test]
+    fn scenes_need_an_object() {
+        let e = parse_err("camera lookfrom=0,0,5 lookat=0,0,0\n");
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (1, "the scene has no objects")
+        );
+        let e = parse_err("material m lambertian albedo=1,1,1\nobject ball sphere center=0,0,0 radius=1 material=m\n# never added");
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (3, "the scene has no objects")
+        );
+    }
+
+    #[test]
+    fn names_must_be_defined_once() {
+        let e = parse_err("material m dielectric ir=1.5\nmaterial m dielectric ir=1.3");
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (2, "material 'm' is already defined")
+        );
+        let e = parse_err("sphere center=0,0,0 radius=1 material=missing");
+        assert_eq!(e.message, "unknown material 'missing'");
+        let e = parse_err("add nothing");
+        assert_eq!(e.message, "unknown object 'nothing'");
+    }
+
+    #[test]
+    fn meshes_cannot_be_lights() {
+        let e = parse_err(
+            "material m diffuse_light emit=1,1,1
+             object ball mesh file=models/icosphere.obj material=m
+             light ball",
+        );
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (3, "'ball' cannot be sampled as a light")
+        );
+    }
+}
@@ -2,7 +2,9 @@ use crate::aabb::Aabb;
 use crate::hittable::HitRecord;
 use crate::hittable::Hittable;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::{cone_pdf, sample_cone};
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
 use std::f64::consts::PI;
@@ -35,6 +37,17 @@ impl Sphere {
         let v = theta / PI;
         (u, v)
     }
+
+    /// Cosine of the half-angle of the cone the sphere fills as seen from
+    /// `origin`, or `None` from inside the sphere.
+    fn cos_theta_max(&self, origin: &Point3) -> Option<f64> {
+        let distance_squared = (self.center - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        Some((1.0 - radius_squared / distance_squared).sqrt())
+    }
 }
 
 impl Hittable for Sphere {
@@ -70,6 +83,30 @@ impl Hittable for Sphere {
         Some(rec)
     }
 
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max)
+                if self
+                    .hit(&Ray::new(origin, direction, 0.0), 0.001, f64::INFINITY)
+                    .is_some() =>
+            {
+                cone_pdf(cos_theta_max)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Samples the cone of directions towards the sphere uniformly.
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        let direction = self.center - *origin;
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => {
+                Onb::build_from_w(&direction).local(&sample_cone(u, cos_theta_max))
+            }
+            None => direction,
+        }
+    }
+
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         let output_box = Aabb::new(
             &(self.center - Vec3::new(self.radius, self.radius, self.radius)),
@@ -77,4 +114,8 @@ impl Hittable for Sphere {
         );
         Some(output_box)
     }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mat_ptr);
+    }
 }
@@ -8,6 +8,7 @@ use std::sync::Arc;
 
 const BYTES_PER_PIXEL: usize = 3;
 
+/// A color that varies over a surface.
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
 }
@@ -45,6 +46,10 @@ pub struct CheckerTexture {
 }
 
 impl CheckerTexture {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>) -> Self {
+        Self { odd, even }
+    }
+
     pub fn new_color(c1: Color, c2: Color) -> Self {
         Self {
             odd: Arc::new(SolidColor::new(c1)),
@@ -95,18 +100,20 @@ pub struct ImageTexture {
 
 impl ImageTexture {
     pub fn new(filename: &str) -> Self {
-        let img = image::open(filename)
-            .expect("ERROR: Could not load texture image file.")
-            .to_rgb8();
+        Self::open(filename).expect("ERROR: Could not load texture image file.")
+    }
+
+    pub fn open(filename: &str) -> Result<Self, image::ImageError> {
+        let img = image::open(filename)?.to_rgb8();
         let width = img.width();
         let height = img.height();
         let data = img.as_raw();
-        Self {
+        Ok(Self {
             data: data.clone(),
             width: width as usize,
             height: height as usize,
             bytes_per_scanline: BYTES_PER_PIXEL * width as usize,
-        }
+        })
     }
 }
 
@@ -0,0 +1,270 @@
+use crate::color::clamp;
+use crate::framebuffer::Image;
+use crate::vec3::Color;
+use image::{Rgb, RgbImage};
+use std::ops::Mul;
+
+/// Default linear white point of the Uncharted 2 curve.
+const UNCHARTED2_WHITE: f64 = 11.2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Clip everything above 1.0.
+    Clamp,
+    /// `L / (1 + L)` on luminance.
+    Reinhard,
+    /// Reinhard with a white point that maps exactly to 1.0; defaults to the
+    /// brightest pixel of the image.
+    ReinhardExtended,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve.
+    Uncharted2,
+}
+
+impl ToneMap {
+    pub const NAMES: [&'static str; 5] = [
+        "clamp",
+        "reinhard",
+        "reinhard_extended",
+        "aces",
+        "uncharted2",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "clamp" => Some(Self::Clamp),
+            "reinhard" => Some(Self::Reinhard),
+            "reinhard_extended" => Some(Self::ReinhardExtended),
+            "aces" => Some(Self::Aces),
+            "uncharted2" => Some(Self::Uncharted2),
+            _ => None,
+        }
+    }
+}
+
+/// Encoding from linear display values to 8-bit code values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    /// The piecewise sRGB curve.
+    Srgb,
+    /// A pure power curve, `c^(1/gamma)`.
+    Gamma(f64),
+}
+
+impl Transfer {
+    pub fn encode(&self, c: f64) -> f64 {
+        match *self {
+            Self::Srgb => {
+                if c <= 0.0031308 {
+                    12.92 * c
+                } else {
+                    1.055 * c.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            Self::Gamma(gamma) => c.powf(1.0 / gamma),
+        }
+    }
+}
+
+/// How linear radiance becomes a displayable 8-bit image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMap,
+    /// Exposure adjustment in stops; each stop doubles the brightness.
+    pub exposure: f64,
+    /// Exposed radiance that maps to display white. `None` uses the
+    /// operator's own default.
+    pub white_point: Option<f64>,
+    pub transfer: Transfer,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMap::Clamp,
+            exposure: 0.0,
+            white_point: None,
+            transfer: Transfer::Srgb,
+        }
+    }
+}
+
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn aces(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn uncharted2(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn per_channel(c: Color, curve: impl Fn(f64) -> f64) -> Color {
+    Color::new(curve(c.x), curve(c.y), curve(c.z))
+}
+
+impl ToneMapping {
+    /// Maps an exposed linear color into display-linear `[0, 1]`.
+    fn map(&self, c: Color, white: Option<f64>) -> Color {
+        match self.operator {
+            ToneMap::Clamp => match white {
+                Some(w) => c / w,
+                None => c,
+            },
+            ToneMap::Reinhard | ToneMap::ReinhardExtended => {
+                let l = luminance(c);
+                if l <= 0.0 {
+                    return Color::zero();
+                }
+                let mapped = match (self.operator, white) {
+                    (ToneMap::ReinhardExtended, Some(w)) => l * (1.0 + l / (w * w)) / (1.0 + l),
+                    (_, Some(w)) => (l / (1.0 + l)) / (w / (1.0 + w)),
+                    (_, None) => l / (1.0 + l),
+                };
+                c.mul(mapped / l)
+            }
+            ToneMap::Aces => {
+                let mapped = per_channel(c, aces);
+                match white {
+                    Some(w) => mapped / aces(w),
+                    None => mapped,
+                }
+            }
+            ToneMap::Uncharted2 => {
+                // Hable's reference implementation biases exposure by 2.
+                let w = white.unwrap_or(UNCHARTED2_WHITE);
+                per_channel(c.mul(2.0), uncharted2) / uncharted2(w)
+            }
+        }
+    }
+
+    /// Tone maps `image` into an 8-bit picture.
+    pub fn apply(&self, image: &Image) -> RgbImage {
+        let scale = 2f64.powf(self.exposure);
+        let white = match (self.operator, self.white_point) {
+            (_, Some(w)) => Some(w),
+            (ToneMap::ReinhardExtended, None) => {
+                let max = image
+                    .pixels
+                    .iter()
+                    .map(|&c| luminance(c))
+                    .fold(0.0, f64::max);
+                Some((max * scale).max(f64::MIN_POSITIVE))
+            }
+            _ => None,
+        };
+        RgbImage::from_fn(image.width as u32, image.height as u32, |x, y| {
+            let exposed = image.get(x as usize, y as usize).mul(scale);
+            // Negative or NaN radiance is treated as black.
+            let exposed = per_channel(exposed, |v| if v > 0.0 { v } else { 0.0 });
+            let mapped = self.map(exposed, white);
+            let encode = |v: f64| {
+                (clamp(self.transfer.encode(clamp(v, 0.0, 1.0)), 0.0, 0.999) * 256.0) as u8
+            };
+            Rgb([encode(mapped.x), encode(mapped.y), encode(mapped.z)])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Greys from black to far above display white.
+    fn greys() -> impl Iterator<Item = f64> {
+        (0..=400).map(|i| 0.001 * 1.05f64.powi(i) - 0.001)
+    }
+
+    #[test]
+    fn srgb_matches_the_reference_curve() {
+        let srgb = Transfer::Srgb;
+        assert_eq!(srgb.encode(0.0), 0.0);
+        assert!((srgb.encode(1.0) - 1.0).abs() < 1e-12);
+        // Both segments meet at the threshold.
+        let threshold = 0.0031308;
+        let linear = 12.92 * threshold;
+        let power = 1.055 * f64::powf(threshold, 1.0 / 2.4) - 0.055;
+        assert!((srgb.encode(threshold) - linear).abs() < 1e-12);
+        assert!((linear - power).abs() < 1e-6);
+        assert!((srgb.encode(threshold + 1e-9) - srgb.encode(threshold)).abs() < 1e-6);
+        // Middle grey.
+        assert!((srgb.encode(0.18) - 0.4613561).abs() < 1e-6);
+        assert!((Transfer::Gamma(2.2).encode(1.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn operators_are_monotonic_and_in_range() {
+        for operator in [ToneMap::Reinhard, ToneMap::Aces, ToneMap::Uncharted2] {
+            let tone_mapping = ToneMapping {
+                operator,
+                ..ToneMapping::default()
+            };
+            let mut previous = 0.0;
+            for v in greys() {
+                let mapped = tone_mapping.map(Color::new(v, v, v), None).x;
+                assert!(mapped >= previous, "{:?} at {}", operator, v);
+                previous = mapped;
+            }
+            assert_eq!(tone_mapping.map(Color::zero(), None), Color::zero());
+        }
+
+        let reinhard = ToneMapping {
+            operator: ToneMap::Reinhard,
+            ..ToneMapping::default()
+        };
+        assert!(greys().all(|v| reinhard.map(Color::new(v, v, v), None).x < 1.0));
+        // The ACES fit passes 1 a little above 7 and levels off just above
+        // it; encoding clips the rest.
+        assert!(aces(7.0) < 1.0 && aces(7.5) > 1.0);
+        assert!(aces(1e6) <= 2.51 / 2.43);
+    }
+
+    #[test]
+    fn white_points_map_to_white() {
+        for operator in [
+            ToneMap::Clamp,
+            ToneMap::Reinhard,
+            ToneMap::ReinhardExtended,
+            ToneMap::Aces,
+            ToneMap::Uncharted2,
+        ] {
+            let tone_mapping = ToneMapping {
+                operator,
+                ..ToneMapping::default()
+            };
+            // Uncharted 2 doubles the exposure before comparing to white.
+            let white = match operator {
+                ToneMap::Uncharted2 => 2.0,
+                _ => 4.0,
+            };
+            let mapped = tone_mapping.map(Color::new(white, white, white), Some(4.0));
+            assert!((mapped.x - 1.0).abs() < 1e-12, "{:?}", operator);
+            let below = tone_mapping.map(Color::new(white, white, white), Some(8.0));
+            assert!(below.x < 1.0, "{:?}", operator);
+        }
+    }
+
+    #[test]
+    fn encoded_images_are_monotonic() {
+        let values: Vec<f64> = greys().collect();
+        let mut image = Image::new(values.len(), 1);
+        for (x, &v) in values.iter().enumerate() {
+            image.set(x, 0, Color::new(v, v, v));
+        }
+        for name in ToneMap::NAMES {
+            let tone_mapping = ToneMapping {
+                operator: ToneMap::from_name(name).unwrap(),
+                ..ToneMapping::default()
+            };
+            let encoded = tone_mapping.apply(&image);
+            let codes: Vec<u8> = encoded.pixels().map(|p| p.0[0]).collect();
+            assert_eq!(codes[0], 0, "{}", name);
+            assert!(codes.windows(2).all(|w| w[0] <= w[1]), "{}", name);
+            assert!(*codes.last().unwrap() >= 250, "{}", name);
+        }
+    }
+}
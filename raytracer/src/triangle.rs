@@ -0,0 +1,216 @@
+use crate::aabb::Aabb;
+use crate::bvh::{BvhNode, BvhStats};
+use crate::hittable::{area_to_solid_angle, HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+use std::ops::{Mul, Sub};
+use std::sync::Arc;
+
+const EPSILON: f64 = 1e-12;
+const PADDING: f64 = 0.0001;
+const INFINITY: f64 = f64::INFINITY;
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    /// Per-vertex normals for smooth shading; the face normal is used if absent.
+    pub normals: Option<[Vec3; 3]>,
+    /// Per-vertex texture coordinates; barycentric coordinates are used if absent.
+    pub uvs: Option<[(f64, f64); 3]>,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, m: Arc<dyn Material>) -> Self {
+        Self {
+            vertices: [v0, v1, v2],
+            normals: None,
+            uvs: None,
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    // Möller–Trumbore ray-triangle intersection.
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let pvec = r.dir.cross(edge2);
+        let det = edge1.dot(pvec);
+        if det.abs() < EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.orig - v0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(edge1);
+        let b2 = r.dir.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+
+        let face_normal = edge1.cross(edge2).unit_vector();
+        let mut rec = HitRecord::new(r.at(t), face_normal, self.mat_ptr.clone(), t);
+        rec.set_face_normal(r, &face_normal);
+        if let Some([n0, n1, n2]) = self.normals {
+            // Keep the interpolated normal on the same side as the face normal.
+            let shading = (n0.mul(b0) + n1.mul(b1) + n2.mul(b2)).unit_vector();
+            rec.normal = if shading.dot(rec.normal) < 0.0 {
+                Vec3::zero().sub(shading)
+            } else {
+                shading
+            };
+        }
+        (rec.u, rec.v) = match self.uvs {
+            Some([t0, t1, t2]) => (
+                b0 * t0.0 + b1 * t1.0 + b2 * t2.0,
+                b0 * t0.1 + b1 * t1.1 + b2 * t2.1,
+            ),
+            None => (b1, b2),
+        };
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        let [v0, v1, v2] = self.vertices;
+        let mut minimum = v0;
+        let mut maximum = v0;
+        for v in [v1, v2] {
+            for a in 0..3 {
+                minimum[a] = minimum[a].min(v[a]);
+                maximum[a] = maximum[a].max(v[a]);
+            }
+        }
+        // Pad so axis-aligned triangles still have a non-empty box.
+        Some(Aabb::new(&(minimum - PADDING), &(maximum + PADDING)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, INFINITY) {
+            Some(rec) => {
+                let [v0, v1, v2] = self.vertices;
+                let area = 0.5 * (v1 - v0).cross(v2 - v0).length();
+                area_to_solid_angle(&rec, direction, area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64)) -> Vec3 {
+        // Uniform barycentric coordinates by folding the unit square.
+        let s = u.0.sqrt();
+        let (b1, b2) = (s * (1.0 - u.1), s * u.1);
+        let [v0, v1, v2] = self.vertices;
+        v0.mul(1.0 - b1 - b2) + v1.mul(b1) + v2.mul(b2) - *origin
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mat_ptr);
+    }
+}
+
+/// A set of triangles behind its own bounding volume hierarchy.
+pub struct TriangleMesh {
+    bvh: Arc<dyn Hittable>,
+    triangle_count: usize,
+}
+
+impl TriangleMesh {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let triangle_count = triangles.len();
+        let objects = triangles
+            .into_iter()
+            .map(|t| Arc::new(t) as Arc<dyn Hittable>)
+            .collect();
+        Self {
+            bvh: BvhNode::new_vec(objects, 0.0, 1.0),
+            triangle_count,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangle_count
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.bvh.bounding_box(time0, time1)
+    }
+
+    fn bvh_stats(&self) -> Option<BvhStats> {
+        self.bvh.bvh_stats()
+    }
+
+    fn materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.bvh.materials(visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::Color;
+
+    fn triangle() -> Triangle {
+        let material = Arc::new(Lambertian::new_color(&Color::new(0.5, 0.5, 0.5)));
+        Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            material,
+        )
+    }
+
+    #[test]
+    fn ray_hits_inside_the_triangle() {
+        let ray = Ray::new(
+            &Point3::new(0.25, 0.25, 2.0),
+            &Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let rec = triangle().hit(&ray, 0.001, INFINITY).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-12);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ray_misses_outside_the_triangle() {
+        let triangle = triangle();
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        let outside = Ray::new(&Point3::new(0.75, 0.75, 2.0), &down, 0.0);
+        assert!(triangle.hit(&outside, 0.001, INFINITY).is_none());
+        let parallel = Ray::new(
+            &Point3::new(-1.0, 0.25, 0.0),
+            &Vec3::new(1.0, 0.0, 0.0),
+            0.0,
+        );
+        assert!(triangle.hit(&parallel, 0.001, INFINITY).is_none());
+        let behind = Ray::new(
+            &Point3::new(0.25, 0.25, 2.0),
+            &Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        assert!(triangle.hit(&behind, 0.001, INFINITY).is_none());
+        let beyond = Ray::new(&Point3::new(0.25, 0.25, 2.0), &down, 0.0);
+        assert!(triangle.hit(&beyond, 0.001, 1.0).is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::rtweekend::{random_f64, random_f64_range};
+use crate::sampler::sample_unit_sphere;
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Sub, SubAssign};
 
 #[derive(Clone, Debug, PartialEq, Copy)]
@@ -91,7 +92,7 @@ impl Vec3 {
     }
 
     pub fn random_unit_vector() -> Self {
-        Self::random_in_unit_sphere().unit_vector()
+        sample_unit_sphere((random_f64(), random_f64()))
     }
 
     pub fn random_in_hemisphere(normal: &Self) -> Self {